@@ -0,0 +1,135 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use crate::{lookup_pattern, ParamValue, ParameterManager, SetOutcome};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccessMode {
+    #[default]
+    ReadWrite,
+    // the first write sets the value, later ones are refused
+    WriteOnce,
+    // never written, the value comes from the rule's default
+    ReadOnly,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessPolicy {
+    pub mode: AccessMode,
+    // When set, only writes presenting this token are accepted. It can be the owning
+    // component's name or a capability handed out by it.
+    pub owner: Option<String>,
+    // left out of listings and exports, still readable by key
+    pub hidden: bool,
+}
+
+impl AccessPolicy {
+    fn accepts(&self, token: Option<&str>) -> bool {
+        self.owner.as_deref().is_none_or(|owner| token == Some(owner))
+    }
+}
+
+impl ParameterManager {
+    // A pattern ending with '*' applies to every key with that prefix; an exact key wins,
+    // then the longest prefix. Taking over from a policy with an owner needs its token, see
+    // set_access_policy_as. false when refused.
+    pub fn set_access_policy(&mut self, pattern: &str, policy: AccessPolicy) -> bool {
+        self.set_access_policy_with_token(pattern, policy, None)
+    }
+
+    pub fn set_access_policy_as(&mut self, token: &str, pattern: &str, policy: AccessPolicy) -> bool {
+        self.set_access_policy_with_token(pattern, policy, Some(token))
+    }
+
+    fn set_access_policy_with_token(&mut self, pattern: &str, policy: AccessPolicy, token: Option<&str>) -> bool {
+        if self.policy_in_force(pattern).is_some_and(|current| !current.accepts(token)) {
+            return false;
+        }
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self.wild_card_access_policies.insert(prefix.to_string(), policy),
+            None => self.access_policies.insert(pattern.to_string(), policy),
+        };
+        true
+    }
+
+    // The policy now in force where a new one for pattern would apply.
+    fn policy_in_force(&self, pattern: &str) -> Option<&AccessPolicy> {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self
+                .wild_card_access_policies
+                .iter()
+                .filter(|(shorter, _)| prefix.starts_with(shorter.as_str()))
+                .max_by_key(|(shorter, _)| shorter.len())
+                .map(|(_, policy)| policy),
+            None => lookup_pattern(&self.access_policies, &self.wild_card_access_policies, pattern),
+        }
+    }
+
+    // Whether a rule for pattern may be installed with token: every owned key it can cover must
+    // belong to the token.
+    pub(crate) fn may_set_rule(&self, pattern: &str, token: Option<&str>) -> bool {
+        let Some(prefix) = pattern.strip_suffix('*') else {
+            return self.policy_in_force(pattern).is_none_or(|policy| policy.accepts(token));
+        };
+        let nested = self
+            .access_policies
+            .iter()
+            .chain(&self.wild_card_access_policies)
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(_, policy)| policy);
+        self.policy_in_force(pattern).into_iter().chain(nested).all(|policy| policy.accepts(token))
+    }
+
+    // The effective policy: the "ro." prefix and read_only rules make a key at least write-once.
+    pub fn get_access_policy(&self, key: &str) -> AccessPolicy {
+        let mut policy = lookup_pattern(&self.access_policies, &self.wild_card_access_policies, key)
+            .cloned()
+            .unwrap_or_default();
+        let write_once = key.starts_with("ro.") || self.rule_for(key).is_some_and(|rule| rule.read_only);
+        if write_once && policy.mode == AccessMode::ReadWrite {
+            policy.mode = AccessMode::WriteOnce;
+        }
+        policy
+    }
+
+    pub fn is_hidden(&self, key: &str) -> bool {
+        lookup_pattern(&self.access_policies, &self.wild_card_access_policies, key).is_some_and(|policy| policy.hidden)
+    }
+
+    pub fn set_parameter_as<T: Into<ParamValue>>(&mut self, token: &str, key: &str, value: T) {
+        self.try_set_parameter_as(token, key, value);
+    }
+
+    // Same as try_set_parameter, presenting token to keys that have an owner.
+    pub fn try_set_parameter_as<T: Into<ParamValue>>(&mut self, token: &str, key: &str, value: T) -> SetOutcome {
+        self.try_set_parameter_with_token(key, value.into(), Some(token))
+    }
+
+    // None when a write with this token is allowed, otherwise the outcome to report.
+    pub(crate) fn check_access(&self, key: &str, token: Option<&str>) -> Option<SetOutcome> {
+        let policy = self.get_access_policy(key);
+        if let Some(owner) = &policy.owner
+            && token != Some(owner.as_str())
+        {
+            return Some(SetOutcome::Denied);
+        }
+        match policy.mode {
+            AccessMode::ReadWrite => None,
+            AccessMode::WriteOnce if !self.params.contains_key(key) => None,
+            _ => Some(SetOutcome::ReadOnly),
+        }
+    }
+}
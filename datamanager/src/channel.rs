@@ -0,0 +1,250 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Listeners that queue changes for the consumer to take from its own loop, instead of running a
+// callback. Changes carry the value in its textual form, as callbacks receive it.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::{ParameterManager, Subscription};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamChange {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    // a full buffer drops its oldest change
+    #[default]
+    DropOldest,
+    // a key is queued at most once with its newest value, in the position of its first change;
+    // a full buffer still drops the oldest key
+    CoalescePerKey,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SubscribeOptions {
+    // None is unbounded
+    pub capacity: Option<usize>,
+    pub overflow: OverflowPolicy,
+}
+
+// Same interface as std::sync::mpsc::Receiver. Unregisters its listener on drop, do not drop it
+// while holding the manager's lock.
+pub struct ChangeReceiver {
+    queue: Arc<ChangeQueue>,
+    _subscription: Subscription,
+}
+
+#[cfg(feature = "tokio")]
+pub struct ChangeStream {
+    queue: Arc<ChangeQueue>,
+    _subscription: Subscription,
+}
+
+struct ChangeQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    changes: VecDeque<ParamChange>,
+    options: SubscribeOptions,
+    dropped: usize,
+    // the listener is gone, by unregister_callback or with the manager
+    closed: bool,
+    #[cfg(feature = "tokio")]
+    waker: Option<Waker>,
+}
+
+// Owned by the listener, closes the queue when the listener is dropped.
+struct ChangeSender(Arc<ChangeQueue>);
+
+impl QueueState {
+    fn push(&mut self, change: ParamChange) {
+        if self.options.overflow == OverflowPolicy::CoalescePerKey
+            && let Some(queued) = self.changes.iter_mut().find(|queued| queued.key == change.key)
+        {
+            queued.value = change.value;
+            return;
+        }
+        if let Some(capacity) = self.options.capacity {
+            while !self.changes.is_empty() && self.changes.len() >= capacity {
+                self.changes.pop_front();
+                self.dropped += 1;
+            }
+            if capacity == 0 {
+                self.dropped += 1;
+                return;
+            }
+        }
+        self.changes.push_back(change);
+    }
+}
+
+impl ChangeSender {
+    fn send(&self, change: ParamChange) {
+        let mut state = self.0.state.lock().unwrap();
+        state.push(change);
+        self.0.wake(&mut state);
+    }
+}
+
+impl Drop for ChangeSender {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.closed = true;
+        self.0.wake(&mut state);
+    }
+}
+
+impl ChangeQueue {
+    fn wake(&self, state: &mut QueueState) {
+        self.available.notify_all();
+        #[cfg(feature = "tokio")]
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        #[cfg(not(feature = "tokio"))]
+        let _ = state;
+    }
+}
+
+impl ChangeReceiver {
+    pub fn recv(&self) -> Result<ParamChange, RecvError> {
+        let state = self.queue.state.lock().unwrap();
+        let mut state = self.queue.available.wait_while(state, |state| state.changes.is_empty() && !state.closed).unwrap();
+        state.changes.pop_front().ok_or(RecvError)
+    }
+
+    pub fn try_recv(&self) -> Result<ParamChange, TryRecvError> {
+        let mut state = self.queue.state.lock().unwrap();
+        match state.changes.pop_front() {
+            Some(change) => Ok(change),
+            None if state.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<ParamChange, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if let Some(change) = state.changes.pop_front() {
+                return Ok(change);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.queue.available.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    // Blocks for every change until the listener is gone.
+    pub fn iter(&self) -> impl Iterator<Item = ParamChange> + '_ {
+        std::iter::from_fn(|| self.recv().ok())
+    }
+
+    // The changes queued right now.
+    pub fn try_iter(&self) -> impl Iterator<Item = ParamChange> + '_ {
+        std::iter::from_fn(|| self.try_recv().ok())
+    }
+
+    // number of changes lost to the overflow policy so far
+    pub fn dropped(&self) -> usize {
+        self.queue.state.lock().unwrap().dropped
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn into_stream(self) -> ChangeStream {
+        ChangeStream {
+            queue: self.queue,
+            _subscription: self._subscription,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio_stream::Stream for ChangeStream {
+    type Item = ParamChange;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<ParamChange>> {
+        let mut state = self.queue.state.lock().unwrap();
+        match state.changes.pop_front() {
+            Some(change) => Poll::Ready(Some(change)),
+            None if state.closed => Poll::Ready(None),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl ParameterManager {
+    // Unbounded, see subscribe_with. pattern is as for register_callback.
+    pub fn subscribe(manager: &Arc<Mutex<ParameterManager>>, pattern: &str) -> ChangeReceiver {
+        Self::subscribe_with(manager, pattern, SubscribeOptions::default())
+    }
+
+    pub fn subscribe_with(manager: &Arc<Mutex<ParameterManager>>, pattern: &str, options: SubscribeOptions) -> ChangeReceiver {
+        let queue = Arc::new(ChangeQueue {
+            state: Mutex::new(QueueState {
+                options,
+                ..Default::default()
+            }),
+            available: Condvar::new(),
+        });
+        let sender = ChangeSender(queue.clone());
+        let subscription = Self::register_subscription(manager, pattern, move |key, value| sender.send(ParamChange { key, value }));
+        ChangeReceiver {
+            queue,
+            _subscription: subscription,
+        }
+    }
+
+    // Starts with the current value of key, None while it has none. Removals are not seen, as
+    // by callbacks. The receiver keeps the last value once the Subscription is dropped.
+    #[cfg(feature = "tokio")]
+    pub fn watch_parameter(manager: &Arc<Mutex<ParameterManager>>, key: &str) -> (tokio::sync::watch::Receiver<Option<String>>, Subscription) {
+        let mut locked = manager.lock().unwrap();
+        // read and registered under one lock, so no change falls in between
+        let (sender, receiver) = tokio::sync::watch::channel(locked.get_parameter_value(key).map(ToString::to_string));
+        let listener_id = locked.register_callback(key, move |_, value| {
+            sender.send_replace(Some(value));
+        });
+        drop(locked);
+        let subscription = Subscription {
+            manager: Arc::downgrade(manager),
+            listener_id: Some(listener_id),
+        };
+        (receiver, subscription)
+    }
+}
@@ -0,0 +1,102 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::borrow::Cow;
+
+use crate::{ParamValue, ParameterManager};
+
+impl ParameterManager {
+    // The rule's default as it would be stored, i.e. converted and clamped by that rule.
+    pub fn get_default(&self, key: &str) -> Option<ParamValue> {
+        let default_value = self.rule_for(key)?.default_value.clone()?;
+        self.check_value_with_rule(key, default_value).ok().map(|(_, stored)| stored)
+    }
+
+    // An absent key counts as default, so does a key without default that was never set.
+    pub fn is_default(&self, key: &str) -> bool {
+        match self.params.get(key) {
+            None => true,
+            Some(value) => self.get_default(key).as_ref() == Some(value),
+        }
+    }
+
+    // Keys the access policy protects from an anonymous write are left untouched. A key without default is removed, without notification.
+    // Returns true when the stored value changed.
+    pub fn reset_parameter(&mut self, key: &str) -> bool {
+        let default_value = self.get_default(key);
+        if self.params.get(key) == default_value.as_ref() || self.check_access(key, None).is_some() {
+            return false;
+        }
+        match default_value {
+            Some(default_value) => self.store_value(key, default_value),
+            None => self.remove_value(key),
+        }
+        true
+    }
+
+    // Resets every stored key with the prefix and applies the defaults of exact rules under it.
+    // Returns the changed keys, sorted.
+    pub fn reset_subtree(&mut self, prefix: &str) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .params
+            .keys()
+            .chain(self.param_rules.keys())
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        self.history.begin_group();
+        keys.retain(|key| self.reset_parameter(key));
+        self.history.end_group();
+        keys
+    }
+
+    pub fn reset_all(&mut self) -> Vec<String> {
+        self.reset_subtree("")
+    }
+
+    // A copy holding only the visible values that differ from their default, e.g. for a compact
+    // export: manager.without_defaults().store_to_file(path)
+    pub fn without_defaults(&self) -> ParameterManager {
+        self.snapshot_where(|key, _| !self.is_default(key) && !self.is_hidden(key))
+    }
+
+    // Values and rules only, no listeners, access policies or file binding.
+    pub(crate) fn snapshot_where<F>(&self, filter: F) -> ParameterManager
+    where
+        F: Fn(&str, &ParamValue) -> bool,
+    {
+        ParameterManager {
+            params: self
+                .params
+                .iter()
+                .filter(|(key, value)| filter(key, value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            param_rules: self.param_rules.clone(),
+            wild_card_rules: self.wild_card_rules.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn value_or_default(&self, key: &str) -> Option<Cow<'_, ParamValue>> {
+        match self.params.get(key) {
+            Some(value) => Some(Cow::Borrowed(value)),
+            None => self.get_default(key).map(Cow::Owned),
+        }
+    }
+}
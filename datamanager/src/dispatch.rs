@@ -0,0 +1,175 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Queued listener dispatch. Notifications are queued per key while the manager is locked and
+// delivered later by the dispatcher thread or the caller's executor, so callbacks are free to
+// lock the manager again. A key has at most one delivery job in flight, which keeps the
+// notifications of a key in order even on an executor running jobs in parallel.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::{BatchListener, Listener, ParamValue, ParameterManager};
+
+pub type DispatchJob = Box<dyn FnOnce() + Send>;
+pub type DispatchExecutor = Arc<dyn Fn(DispatchJob) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub enum DispatchMode {
+    // callbacks run inside set_parameter, while the caller holds the manager
+    #[default]
+    Inline,
+    // one dispatcher thread owned by the manager
+    Thread,
+    // e.g. a thread pool: DispatchMode::Executor(Arc::new(move |job| pool.execute(job)))
+    Executor(DispatchExecutor),
+}
+
+pub(crate) struct Dispatcher {
+    shared: Arc<DispatchShared>,
+    executor: Option<DispatchExecutor>,
+}
+
+struct DispatchShared {
+    state: Mutex<DispatchState>,
+    idle: Condvar,
+}
+
+#[derive(Default)]
+struct DispatchState {
+    // Keyed by parameter key, None is the queue of batch listeners. A queue is present while
+    // its delivery job is scheduled or running.
+    queues: HashMap<Option<String>, VecDeque<DispatchJob>>,
+    pending: usize,
+}
+
+impl Dispatcher {
+    // None for DispatchMode::Inline
+    fn new(mode: DispatchMode) -> Option<Self> {
+        let executor = match mode {
+            DispatchMode::Inline => return None,
+            DispatchMode::Executor(executor) => executor,
+            DispatchMode::Thread => {
+                let (sender, receiver) = mpsc::channel::<DispatchJob>();
+                // ends once the executor, the only sender, is dropped
+                thread::spawn(move || {
+                    for job in receiver {
+                        job();
+                    }
+                });
+                let executor: DispatchExecutor = Arc::new(move |job| {
+                    let _ = sender.send(job);
+                });
+                executor
+            }
+        };
+        Some(Dispatcher {
+            shared: Arc::new(DispatchShared {
+                state: Mutex::new(DispatchState::default()),
+                idle: Condvar::new(),
+            }),
+            executor: Some(executor),
+        })
+    }
+
+    pub(crate) fn enqueue(&self, key: &str, value: String, listeners: Vec<Listener>) {
+        if listeners.is_empty() {
+            return;
+        }
+        let notify_key = key.to_string();
+        self.enqueue_job(Some(key.to_string()), Box::new(move || {
+            for listener in &listeners {
+                (listener.callback.lock().unwrap())(notify_key.clone(), value.clone());
+            }
+        }));
+    }
+
+    pub(crate) fn enqueue_batch(&self, changes: Vec<(String, ParamValue)>, listeners: Vec<BatchListener>) {
+        if listeners.is_empty() {
+            return;
+        }
+        self.enqueue_job(None, Box::new(move || {
+            for listener in &listeners {
+                (listener.callback.lock().unwrap())(changes.clone());
+            }
+        }));
+    }
+
+    fn enqueue_job(&self, queue: Option<String>, job: DispatchJob) {
+        let start = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.pending += 1;
+            let start = !state.queues.contains_key(&queue);
+            state.queues.entry(queue.clone()).or_default().push_back(job);
+            start
+        };
+        if let (true, Some(executor)) = (start, &self.executor) {
+            let shared = self.shared.clone();
+            executor(Box::new(move || Self::deliver(&shared, &queue)));
+        }
+    }
+
+    fn deliver(shared: &DispatchShared, queue: &Option<String>) {
+        loop {
+            let job = {
+                let mut state = shared.state.lock().unwrap();
+                match state.queues.get_mut(queue).and_then(VecDeque::pop_front) {
+                    Some(job) => job,
+                    None => {
+                        state.queues.remove(queue);
+                        return;
+                    }
+                }
+            };
+            job();
+            let mut state = shared.state.lock().unwrap();
+            state.pending -= 1;
+            if state.pending == 0 {
+                shared.idle.notify_all();
+            }
+        }
+    }
+
+    fn wait_idle(&self) {
+        let state = self.shared.state.lock().unwrap();
+        let _state = self.shared.idle.wait_while(state, |state| state.pending > 0).unwrap();
+    }
+}
+
+impl Drop for Dispatcher {
+    // The dispatcher thread delivers what is queued, then exits. It is not joined: the last
+    // reference usually goes away under the manager's lock, which a queued callback may be waiting for.
+    fn drop(&mut self) {
+        self.executor = None;
+    }
+}
+
+impl ParameterManager {
+    // Notifications already queued by the previous mode are still delivered.
+    pub fn set_dispatch_mode(&mut self, mode: DispatchMode) {
+        self.dispatcher = Dispatcher::new(mode).map(Arc::new);
+    }
+
+    // Blocks until every queued notification has been delivered. Takes the shared manager so the
+    // lock is not held while waiting; do not call it from a callback.
+    pub fn wait_for_notifications(manager: &Arc<Mutex<ParameterManager>>) {
+        let dispatcher = manager.lock().unwrap().dispatcher.clone();
+        if let Some(dispatcher) = dispatcher {
+            dispatcher.wait_idle();
+        }
+    }
+}
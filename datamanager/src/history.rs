@@ -0,0 +1,234 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Undo/redo history and named snapshots. Every set, reset or removal is one step; a transaction,
+// a subtree operation, a reload or a snapshot restore is recorded as a single step.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::{ParamValue, ParameterManager};
+
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+// Outcome of undo, redo and restore_snapshot, keys sorted. Keys whose access policy refuses an
+// anonymous write are left as they are and reported in refused.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RestoreReport {
+    pub changed: Vec<String>,
+    pub refused: Vec<String>,
+}
+
+// None stands for an absent key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamDiff {
+    pub key: String,
+    pub before: Option<ParamValue>,
+    pub after: Option<ParamValue>,
+}
+
+#[derive(Clone)]
+pub(crate) struct History {
+    undo: VecDeque<Vec<ParamDiff>>,
+    redo: Vec<Vec<ParamDiff>>,
+    limit: usize,
+    group_depth: usize,
+    group: Vec<ParamDiff>,
+    snapshots: HashMap<String, HashMap<String, ParamValue>>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit: DEFAULT_HISTORY_LIMIT,
+            group_depth: 0,
+            group: Vec::new(),
+            snapshots: HashMap::new(),
+        }
+    }
+}
+
+impl History {
+    pub(crate) fn record(&mut self, key: &str, before: Option<ParamValue>, after: Option<ParamValue>) {
+        if self.limit == 0 || before == after {
+            return;
+        }
+        let change = ParamDiff {
+            key: key.to_string(),
+            before,
+            after,
+        };
+        if self.group_depth > 0 {
+            self.group.push(change);
+        } else {
+            self.push(vec![change]);
+        }
+    }
+
+    // Groups nest, the outermost one becomes the step.
+    pub(crate) fn begin_group(&mut self) {
+        self.group_depth += 1;
+    }
+
+    pub(crate) fn end_group(&mut self) {
+        self.group_depth -= 1;
+        if self.group_depth == 0 && !self.group.is_empty() {
+            let group = std::mem::take(&mut self.group);
+            self.push(group);
+        }
+    }
+
+    fn push(&mut self, step: Vec<ParamDiff>) {
+        self.redo.clear();
+        self.undo.push_back(step);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+}
+
+fn diff_values(before: &HashMap<String, ParamValue>, after: &HashMap<String, ParamValue>) -> Vec<ParamDiff> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| ParamDiff {
+            key: key.clone(),
+            before: before.get(key).cloned(),
+            after: after.get(key).cloned(),
+        })
+        .collect()
+}
+
+impl ParameterManager {
+    // Number of steps kept, 0 disables the history. Older steps are dropped first.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.limit = limit;
+        while self.history.undo.len() > limit {
+            self.history.undo.pop_front();
+        }
+        self.history.redo.truncate(limit);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo.is_empty()
+    }
+
+    // Reverts the last step. Rules and veto listeners are bypassed, the values were accepted when
+    // first stored, but access policies are not. Refused keys are dropped from the step, so a redo
+    // does not bring them back either.
+    pub fn undo(&mut self) -> RestoreReport {
+        let Some(step) = self.history.undo.pop_back() else {
+            return RestoreReport::default();
+        };
+        let (step, refused) = self.split_refused(step);
+        let changed = self.replay(step.iter().rev().map(|change| (change.key.clone(), change.before.clone())).collect());
+        if !step.is_empty() {
+            self.history.redo.push(step);
+        }
+        RestoreReport { changed, refused }
+    }
+
+    pub fn redo(&mut self) -> RestoreReport {
+        let Some(step) = self.history.redo.pop() else {
+            return RestoreReport::default();
+        };
+        let (step, refused) = self.split_refused(step);
+        let changed = self.replay(step.iter().map(|change| (change.key.clone(), change.after.clone())).collect());
+        if !step.is_empty() {
+            self.history.undo.push_back(step);
+        }
+        RestoreReport { changed, refused }
+    }
+
+    // Captures every value, replacing a snapshot of the same name.
+    pub fn snapshot(&mut self, name: &str) {
+        self.history.snapshots.insert(name.to_string(), self.params.clone());
+    }
+
+    pub fn remove_snapshot(&mut self, name: &str) -> bool {
+        self.history.snapshots.remove(name).is_some()
+    }
+
+    pub fn snapshot_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.history.snapshots.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Brings every value back to the snapshot, removing keys set since. Only the keys that change
+    // are notified, and the restore is a single undo step. None when there is no such snapshot.
+    pub fn restore_snapshot(&mut self, name: &str) -> Option<RestoreReport> {
+        let snapshot = self.history.snapshots.get(name)?;
+        let (changes, refused) = self.split_refused(diff_values(&self.params, snapshot));
+        self.history.begin_group();
+        for change in &changes {
+            self.history.record(&change.key, change.before.clone(), change.after.clone());
+        }
+        self.history.end_group();
+        let changed = self.replay(changes.into_iter().map(|change| (change.key, change.after)).collect());
+        Some(RestoreReport { changed, refused })
+    }
+
+    // Keys that differ from snapshot a to snapshot b, sorted. None when either one is missing.
+    pub fn diff_snapshot(&self, a: &str, b: &str) -> Option<Vec<ParamDiff>> {
+        Some(diff_values(self.history.snapshots.get(a)?, self.history.snapshots.get(b)?))
+    }
+
+    // Separates the changes to keys an anonymous write may not touch, returned sorted.
+    fn split_refused(&self, changes: Vec<ParamDiff>) -> (Vec<ParamDiff>, Vec<String>) {
+        let (allowed, refused): (Vec<ParamDiff>, Vec<ParamDiff>) = changes.into_iter().partition(|change| self.check_access(&change.key, None).is_none());
+        let refused: BTreeSet<String> = refused.into_iter().map(|change| change.key).collect();
+        (allowed, refused.into_iter().collect())
+    }
+
+    // Puts the values back without recording them, then persists once and notifies the keys
+    // that got a value. Returns the keys that changed, sorted.
+    pub(crate) fn replay(&mut self, values: Vec<(String, Option<ParamValue>)>) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (key, value) in values {
+            let before = match &value {
+                Some(value) => self.params.insert(key.clone(), value.clone()),
+                None => self.params.remove(&key),
+            };
+            if before != value {
+                self.journal_change(&key, before.as_ref(), value.as_ref());
+                changed.push((key, value));
+            }
+        }
+        self.schedule_persist_keys(changed.iter().map(|(key, _)| key.as_str()));
+        for (key, value) in &changed {
+            if let Some(value) = value {
+                self.notify(key, value.to_string());
+            }
+        }
+        let mut keys: Vec<String> = changed.into_iter().map(|(key, _)| key).collect();
+        keys.sort();
+        keys
+    }
+
+    // Removes a value as one history step; removals are not notified.
+    pub(crate) fn remove_value(&mut self, key: &str) {
+        let before = self.params.remove(key);
+        self.journal_change(key, before.as_ref(), None);
+        self.history.record(key, before, None);
+        self.schedule_persist(key);
+    }
+}
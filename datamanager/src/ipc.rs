@@ -0,0 +1,445 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Shares a ParameterManager with other processes over a Unix domain socket.
+//
+// Every frame is a 4 byte big endian length followed by a JSON object of that length.
+// Requests carry an id that the response repeats:
+//   {"id":1,"op":"get","key":"audio.volume"}          -> {"id":1,"value":5}  ("value" absent if unset)
+//   {"id":2,"op":"set","key":"audio.volume","value":7,"token":"audio"}  ("token" optional)
+//                                                     -> {"id":2,"outcome":"Clamped","from":70,"to":10}
+//   {"id":3,"op":"list","prefix":"audio."}            -> {"id":3,"keys":["audio.volume"]}
+//   {"id":4,"op":"subscribe","pattern":"audio.*"}     -> {"id":4}
+//   {"id":5,"op":"unsubscribe","subscription":4}      -> {"id":5,"removed":true}
+// A failed request gets {"id":n,"error":"message"}. A subscription is named by the id of its
+// subscribe request, which is refused if in use, and pushes {"event":4,"key":"audio.volume","value":"7"} for every change,
+// the value in its textual form as register_callback passes it.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use serde_json::{Map, Value};
+
+use crate::json::{json_to_value, value_to_json};
+use crate::{ParamError, ParamValue, ParameterManager, RejectReason, SetOutcome};
+
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+// a stream clone to shut each connection down with, and its worker
+type Connections = Arc<Mutex<Vec<(UnixStream, thread::JoinHandle<()>)>>>;
+
+pub struct ParamServer {
+    path: PathBuf,
+    stopping: Arc<AtomicBool>,
+    acceptor: Option<thread::JoinHandle<()>>,
+    connections: Connections,
+}
+
+type EventCallback = Arc<dyn Fn(String, String) + Send + Sync>;
+
+// A remote ParameterManager. Callbacks run in order on a delivery thread of their own, so they
+// may call the client again.
+pub struct ParamClient {
+    writer: Mutex<UnixStream>,
+    next_id: AtomicU64,
+    pending: Arc<Mutex<HashMap<u64, mpsc::Sender<Value>>>>,
+    subscriptions: Arc<Mutex<HashMap<u64, EventCallback>>>,
+    reader: Option<thread::JoinHandle<()>>,
+    delivery: Option<thread::JoinHandle<()>>,
+}
+
+fn write_frame<W: Write>(writer: &mut W, frame: &Value) -> io::Result<()> {
+    let payload = frame.to_string();
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload.as_bytes())?;
+    writer.flush()
+}
+
+// None on a clean end of stream
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes", len)));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    serde_json::from_slice(&payload).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn outcome_to_json(outcome: SetOutcome, response: &mut Map<String, Value>) {
+    let name = match outcome {
+        SetOutcome::Stored => "Stored",
+        SetOutcome::Unchanged => "Unchanged",
+        SetOutcome::Clamped { from, to } => {
+            response.insert("from".to_string(), value_to_json(&from));
+            response.insert("to".to_string(), value_to_json(&to));
+            "Clamped"
+        }
+        SetOutcome::Rejected { reason } => {
+            response.insert("reason".to_string(), Value::from(reason.to_string()));
+            "Rejected"
+        }
+        SetOutcome::ReadOnly => "ReadOnly",
+        SetOutcome::Denied => "Denied",
+    };
+    response.insert("outcome".to_string(), Value::from(name));
+}
+
+fn json_to_outcome(key: &str, response: &Value) -> Result<SetOutcome, ParamError> {
+    let value_of = |name: &str| json_to_value(key, response.get(name).unwrap_or(&Value::Null));
+    match response.get("outcome").and_then(Value::as_str) {
+        Some("Stored") => Ok(SetOutcome::Stored),
+        Some("Unchanged") => Ok(SetOutcome::Unchanged),
+        Some("Clamped") => Ok(SetOutcome::Clamped {
+            from: value_of("from")?,
+            to: value_of("to")?,
+        }),
+        // only the text of the reason crosses the socket
+        Some("Rejected") => Ok(SetOutcome::Rejected {
+            reason: RejectReason::Invalid(response.get("reason").and_then(Value::as_str).unwrap_or_default().to_string()),
+        }),
+        Some("ReadOnly") => Ok(SetOutcome::ReadOnly),
+        Some("Denied") => Ok(SetOutcome::Denied),
+        _ => Err(ParamError::Io(format!("unexpected response {}", response))),
+    }
+}
+
+fn io_param_error(err: io::Error) -> ParamError {
+    ParamError::Io(err.to_string())
+}
+
+impl ParamServer {
+    // Fails if path exists, remove a stale socket first. The socket file is removed on drop.
+    pub fn bind<P: AsRef<Path>>(manager: &Arc<Mutex<ParameterManager>>, path: P) -> io::Result<ParamServer> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let acceptor = {
+            let manager = manager.clone();
+            let stopping = stopping.clone();
+            let connections = connections.clone();
+            thread::spawn(move || Self::accept(listener, manager, stopping, connections))
+        };
+        Ok(ParamServer {
+            path,
+            stopping,
+            acceptor: Some(acceptor),
+            connections,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Closes every connection and removes the socket file. Do not call it while holding the
+    // manager's lock, connections unregister their listeners on the way out.
+    pub fn shutdown(&mut self) {
+        let Some(acceptor) = self.acceptor.take() else {
+            return;
+        };
+        self.stopping.store(true, Ordering::SeqCst);
+        // wake the acceptor up
+        let _ = UnixStream::connect(&self.path);
+        let _ = acceptor.join();
+        let connections = std::mem::take(&mut *self.connections.lock().unwrap());
+        for (stream, worker) in connections {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+            let _ = worker.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+
+    fn accept(listener: UnixListener, manager: Arc<Mutex<ParameterManager>>, stopping: Arc<AtomicBool>, connections: Connections) {
+        for stream in listener.incoming() {
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let Ok(handle) = stream.try_clone() else {
+                continue;
+            };
+            let manager = manager.clone();
+            let worker = thread::spawn(move || Self::serve(stream, manager));
+            let mut connections = connections.lock().unwrap();
+            connections.retain(|(_, worker)| !worker.is_finished());
+            connections.push((handle, worker));
+        }
+    }
+
+    fn serve(stream: UnixStream, manager: Arc<Mutex<ParameterManager>>) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+        // responses and events share one writer thread, so a slow client never blocks the
+        // listeners running under the manager's lock
+        let (sender, receiver) = mpsc::channel::<Value>();
+        let writer_thread = thread::spawn(move || {
+            for frame in receiver {
+                if write_frame(&mut writer, &frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut reader = stream;
+        let mut subscriptions: HashMap<u64, usize> = HashMap::new();
+        while let Ok(Some(request)) = read_frame(&mut reader) {
+            let response = Self::handle(&request, &manager, &sender, &mut subscriptions);
+            if sender.send(Value::Object(response)).is_err() {
+                break;
+            }
+        }
+
+        let mut manager = manager.lock().unwrap();
+        for listener_id in subscriptions.into_values() {
+            manager.unregister_callback(listener_id);
+        }
+        drop(manager);
+        drop(sender);
+        let _ = writer_thread.join();
+    }
+
+    fn handle(request: &Value, manager: &Arc<Mutex<ParameterManager>>, sender: &mpsc::Sender<Value>, subscriptions: &mut HashMap<u64, usize>) -> Map<String, Value> {
+        let id = request.get("id").and_then(Value::as_u64).unwrap_or(0);
+        let mut response = Map::new();
+        response.insert("id".to_string(), Value::from(id));
+        let text = |name: &str| request.get(name).and_then(Value::as_str);
+
+        let result: Result<(), String> = match request.get("op").and_then(Value::as_str) {
+            Some("get") => match text("key") {
+                Some(key) => {
+                    if let Some(value) = manager.lock().unwrap().get_parameter_value(key) {
+                        response.insert("value".to_string(), value_to_json(value));
+                    }
+                    Ok(())
+                }
+                None => Err("missing key".to_string()),
+            },
+            Some("set") => match (text("key"), request.get("value")) {
+                (Some(key), Some(value)) => json_to_value(key, value).map_err(|err| err.to_string()).map(|value| {
+                    let mut manager = manager.lock().unwrap();
+                    let outcome = match text("token") {
+                        Some(token) => manager.try_set_parameter_as(token, key, value),
+                        None => manager.try_set_parameter(key, value),
+                    };
+                    outcome_to_json(outcome, &mut response);
+                }),
+                _ => Err("missing key or value".to_string()),
+            },
+            Some("list") => {
+                let keys = manager.lock().unwrap().keys_with_prefix(text("prefix").unwrap_or(""));
+                response.insert("keys".to_string(), Value::from(keys));
+                Ok(())
+            }
+            Some("subscribe") => match text("pattern") {
+                // the client names the subscription, an id in use would leak its listener
+                Some(_) if subscriptions.contains_key(&id) => Err(format!("subscription {} exists", id)),
+                Some(pattern) => {
+                    let sender = sender.clone();
+                    let listener_id = manager.lock().unwrap().register_callback(pattern, move |key, value| {
+                        let mut event = Map::new();
+                        event.insert("event".to_string(), Value::from(id));
+                        event.insert("key".to_string(), Value::from(key));
+                        event.insert("value".to_string(), Value::from(value));
+                        let _ = sender.send(Value::Object(event));
+                    });
+                    subscriptions.insert(id, listener_id);
+                    Ok(())
+                }
+                None => Err("missing pattern".to_string()),
+            },
+            Some("unsubscribe") => match request.get("subscription").and_then(Value::as_u64) {
+                Some(subscription) => {
+                    let removed = match subscriptions.remove(&subscription) {
+                        Some(listener_id) => manager.lock().unwrap().unregister_callback(listener_id),
+                        None => false,
+                    };
+                    response.insert("removed".to_string(), Value::from(removed));
+                    Ok(())
+                }
+                None => Err("missing subscription".to_string()),
+            },
+            _ => Err("unknown op".to_string()),
+        };
+        if let Err(message) = result {
+            response.insert("error".to_string(), Value::from(message));
+        }
+        response
+    }
+}
+
+impl Drop for ParamServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl ParamClient {
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<ParamClient> {
+        let stream = UnixStream::connect(path)?;
+        let mut reader = stream.try_clone()?;
+        let pending: Arc<Mutex<HashMap<u64, mpsc::Sender<Value>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let subscriptions: Arc<Mutex<HashMap<u64, EventCallback>>> = Arc::new(Mutex::new(HashMap::new()));
+        let (events, received) = mpsc::channel::<(u64, String, String)>();
+        // ends with the reader, which owns the only sender
+        let delivery_thread = {
+            let subscriptions = subscriptions.clone();
+            thread::spawn(move || {
+                for (subscription, key, value) in received {
+                    let callback = subscriptions.lock().unwrap().get(&subscription).cloned();
+                    if let Some(callback) = callback {
+                        callback(key, value);
+                    }
+                }
+            })
+        };
+        let reader_thread = {
+            let pending = pending.clone();
+            thread::spawn(move || {
+                while let Ok(Some(frame)) = read_frame(&mut reader) {
+                    if let Some(subscription) = frame.get("event").and_then(Value::as_u64) {
+                        let text = |name: &str| frame.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+                        let _ = events.send((subscription, text("key"), text("value")));
+                    } else if let Some(id) = frame.get("id").and_then(Value::as_u64)
+                        && let Some(waiter) = pending.lock().unwrap().remove(&id)
+                    {
+                        let _ = waiter.send(frame);
+                    }
+                }
+                // wake up every caller still waiting, their senders are dropped here
+                pending.lock().unwrap().clear();
+            })
+        };
+        Ok(ParamClient {
+            writer: Mutex::new(stream),
+            next_id: AtomicU64::new(1),
+            pending,
+            subscriptions,
+            reader: Some(reader_thread),
+            delivery: Some(delivery_thread),
+        })
+    }
+
+    fn request(&self, op: &str, fields: Vec<(&str, Value)>) -> Result<Value, ParamError> {
+        self.request_with_id(self.next_id.fetch_add(1, Ordering::SeqCst), op, fields)
+    }
+
+    fn request_with_id(&self, id: u64, op: &str, fields: Vec<(&str, Value)>) -> Result<Value, ParamError> {
+        let mut request = Map::new();
+        request.insert("id".to_string(), Value::from(id));
+        request.insert("op".to_string(), Value::from(op));
+        for (name, value) in fields {
+            request.insert(name.to_string(), value);
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        if let Err(err) = write_frame(&mut *self.writer.lock().unwrap(), &Value::Object(request)) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(io_param_error(err));
+        }
+        let response = receiver.recv().map_err(|_| ParamError::Io("connection closed".to_string()))?;
+        match response.get("error").and_then(Value::as_str) {
+            Some(message) => Err(ParamError::Io(message.to_string())),
+            None => Ok(response),
+        }
+    }
+
+    pub fn get_parameter_value(&self, key: &str) -> Result<Option<ParamValue>, ParamError> {
+        let response = self.request("get", vec![("key", Value::from(key))])?;
+        response.get("value").map(|value| json_to_value(key, value)).transpose()
+    }
+
+    pub fn set_parameter<T: Into<ParamValue>>(&self, key: &str, value: T) -> Result<(), ParamError> {
+        self.try_set_parameter(key, value).map(|_| ())
+    }
+
+    // Rejection reasons arrive as RejectReason::Invalid holding their text.
+    pub fn try_set_parameter<T: Into<ParamValue>>(&self, key: &str, value: T) -> Result<SetOutcome, ParamError> {
+        let response = self.request("set", vec![("key", Value::from(key)), ("value", value_to_json(&value.into()))])?;
+        json_to_outcome(key, &response)
+    }
+
+    pub fn try_set_parameter_as<T: Into<ParamValue>>(&self, token: &str, key: &str, value: T) -> Result<SetOutcome, ParamError> {
+        let response = self.request("set", vec![("key", Value::from(key)), ("value", value_to_json(&value.into())), ("token", Value::from(token))])?;
+        json_to_outcome(key, &response)
+    }
+
+    pub fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, ParamError> {
+        let response = self.request("list", vec![("prefix", Value::from(prefix))])?;
+        Ok(response
+            .get("keys")
+            .and_then(Value::as_array)
+            .map(|keys| keys.iter().filter_map(Value::as_str).map(str::to_string).collect())
+            .unwrap_or_default())
+    }
+
+    pub fn keys(&self) -> Result<Vec<String>, ParamError> {
+        self.keys_with_prefix("")
+    }
+
+    // Takes the same patterns as ParameterManager::register_callback.
+    pub fn register_callback<F>(&self, key: &str, callback: F) -> Result<u64, ParamError>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        // in place before the request, events may arrive ahead of the response
+        self.subscriptions.lock().unwrap().insert(id, Arc::new(callback));
+        match self.request_with_id(id, "subscribe", vec![("pattern", Value::from(key))]) {
+            Ok(_) => Ok(id),
+            Err(err) => {
+                self.subscriptions.lock().unwrap().remove(&id);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn unregister_callback(&self, subscription: u64) -> Result<bool, ParamError> {
+        self.subscriptions.lock().unwrap().remove(&subscription);
+        let response = self.request("unsubscribe", vec![("subscription", Value::from(subscription))])?;
+        Ok(response.get("removed").and_then(Value::as_bool).unwrap_or(false))
+    }
+}
+
+impl Drop for ParamClient {
+    fn drop(&mut self) {
+        let _ = self.writer.lock().unwrap().shutdown(std::net::Shutdown::Both);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        // a callback may hold the last reference to the client
+        if let Some(delivery) = self.delivery.take()
+            && delivery.thread().id() != thread::current().id()
+        {
+            let _ = delivery.join();
+        }
+    }
+}
@@ -0,0 +1,329 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Append-only change journal, one JSON object per line:
+//   {"ts":1735689600000,"key":"audio.volume","old":5,"new":7,"tag":"tuning-ui"}
+//   {"ts":1735689600000,"snapshot":{"audio.volume":7,"audio.mode":"high"}}
+// "old" or "new" is left out when the key was absent before or is removed by the change.
+// Compaction replaces the whole file with one snapshot line. A last line cut short by a crash is
+// dropped when the journal is opened again.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+
+use crate::json::{json_to_value, value_to_json};
+use crate::persist::{io_error, write_atomically};
+use crate::{ParamError, ParamValue, ParameterManager};
+
+#[derive(Clone, Debug)]
+pub struct JournalOptions {
+    // the journal is compacted once it holds more change records than this, 0 never compacts
+    pub compact_after: usize,
+    // fsync after every record
+    pub sync: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalRecord {
+    pub key: String,
+    pub old: Option<ParamValue>,
+    pub new: Option<ParamValue>,
+    pub timestamp: SystemTime,
+    pub tag: Option<String>,
+}
+
+pub(crate) struct Journal {
+    path: PathBuf,
+    options: JournalOptions,
+    state: Mutex<JournalState>,
+}
+
+struct JournalState {
+    file: File,
+    records: usize,
+    last_error: Option<ParamError>,
+}
+
+enum JournalLine {
+    Snapshot(Vec<(String, ParamValue)>),
+    Change(JournalRecord),
+}
+
+struct JournalContents {
+    lines: Vec<JournalLine>,
+    // length of the well formed part of the file
+    valid_len: u64,
+}
+
+impl Default for JournalOptions {
+    fn default() -> Self {
+        JournalOptions {
+            compact_after: 1000,
+            sync: false,
+        }
+    }
+}
+
+fn timestamp_millis(timestamp: SystemTime) -> u64 {
+    timestamp.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0)
+}
+
+fn format_change(record: &JournalRecord) -> String {
+    let mut line = Map::new();
+    line.insert("ts".to_string(), Value::from(timestamp_millis(record.timestamp)));
+    line.insert("key".to_string(), Value::from(record.key.as_str()));
+    if let Some(old) = &record.old {
+        line.insert("old".to_string(), value_to_json(old));
+    }
+    if let Some(new) = &record.new {
+        line.insert("new".to_string(), value_to_json(new));
+    }
+    if let Some(tag) = &record.tag {
+        line.insert("tag".to_string(), Value::from(tag.as_str()));
+    }
+    Value::Object(line).to_string()
+}
+
+fn format_snapshot(params: &[(&String, &ParamValue)]) -> String {
+    let snapshot: Map<String, Value> = params.iter().map(|(key, value)| (key.to_string(), value_to_json(value))).collect();
+    let mut line = Map::new();
+    line.insert("ts".to_string(), Value::from(timestamp_millis(SystemTime::now())));
+    line.insert("snapshot".to_string(), Value::Object(snapshot));
+    Value::Object(line).to_string()
+}
+
+fn parse_line(line_no: usize, line: &str) -> Result<JournalLine, ParamError> {
+    let error = |message: &str| ParamError::Parse {
+        line: line_no,
+        message: message.to_string(),
+    };
+    let value: Value = serde_json::from_str(line).map_err(|err| error(&err.to_string()))?;
+    let object = value.as_object().ok_or_else(|| error("a record must be an object"))?;
+    let timestamp = UNIX_EPOCH + Duration::from_millis(object.get("ts").and_then(Value::as_u64).unwrap_or(0));
+
+    if let Some(snapshot) = object.get("snapshot") {
+        let snapshot = snapshot.as_object().ok_or_else(|| error("snapshot must be an object"))?;
+        let values = snapshot
+            .iter()
+            .map(|(key, value)| json_to_value(key, value).map(|value| (key.clone(), value)))
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(JournalLine::Snapshot(values));
+    }
+
+    let key = object.get("key").and_then(Value::as_str).ok_or_else(|| error("missing key"))?;
+    let value_of = |name: &str| object.get(name).map(|value| json_to_value(key, value)).transpose();
+    Ok(JournalLine::Change(JournalRecord {
+        key: key.to_string(),
+        old: value_of("old")?,
+        new: value_of("new")?,
+        timestamp,
+        tag: object.get("tag").and_then(Value::as_str).map(str::to_string),
+    }))
+}
+
+// A malformed last line is taken for a torn write and ignored, anywhere else it is an error.
+fn read_contents(path: &Path) -> Result<JournalContents, Vec<ParamError>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(JournalContents {
+                lines: Vec::new(),
+                valid_len: 0,
+            });
+        }
+        Err(err) => return Err(vec![io_error(path, err)]),
+    };
+    let mut reader = BufReader::new(file);
+    let mut contents = JournalContents {
+        lines: Vec::new(),
+        valid_len: 0,
+    };
+    let mut pending_error = None;
+    let mut buffer = String::new();
+    let mut line_no = 0;
+    loop {
+        buffer.clear();
+        let read = reader.read_line(&mut buffer).map_err(|err| vec![io_error(path, err)])?;
+        if read == 0 {
+            break;
+        }
+        line_no += 1;
+        if let Some(err) = pending_error.take() {
+            return Err(vec![err]);
+        }
+        let complete = buffer.ends_with('\n');
+        let line = buffer.trim();
+        if line.is_empty() {
+            contents.valid_len += read as u64;
+            continue;
+        }
+        match parse_line(line_no, line) {
+            Ok(parsed) if complete => {
+                contents.lines.push(parsed);
+                contents.valid_len += read as u64;
+            }
+            // an unterminated last line may still miss bytes, even when it parses
+            Ok(_) => {}
+            Err(err) => pending_error = Some(err),
+        }
+    }
+    Ok(contents)
+}
+
+pub fn read_journal<P: AsRef<Path>>(path: P) -> Result<Vec<JournalRecord>, Vec<ParamError>> {
+    let contents = read_contents(path.as_ref())?;
+    Ok(contents
+        .lines
+        .into_iter()
+        .filter_map(|line| match line {
+            JournalLine::Change(record) => Some(record),
+            JournalLine::Snapshot(_) => None,
+        })
+        .collect())
+}
+
+impl Journal {
+    fn open(path: PathBuf, options: JournalOptions, valid_len: u64, records: usize) -> Result<Self, ParamError> {
+        let file = OpenOptions::new().create(true).append(true).open(&path).map_err(|err| io_error(&path, err))?;
+        // drop a torn last record so the next one starts on a line of its own
+        if file.metadata().map_err(|err| io_error(&path, err))?.len() > valid_len {
+            file.set_len(valid_len).map_err(|err| io_error(&path, err))?;
+        }
+        Ok(Journal {
+            path,
+            options,
+            state: Mutex::new(JournalState {
+                file,
+                records,
+                last_error: None,
+            }),
+        })
+    }
+
+    // true when the journal has grown past the compaction threshold
+    fn append(&self, record: &JournalRecord) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let line = format_change(record);
+        let result = writeln!(state.file, "{}", line).and_then(|_| if self.options.sync { state.file.sync_data() } else { Ok(()) });
+        match result {
+            Ok(()) => state.records += 1,
+            Err(err) => state.last_error = Some(io_error(&self.path, err)),
+        }
+        self.options.compact_after > 0 && state.records > self.options.compact_after
+    }
+
+    fn compact(&self, params: &[(&String, &ParamValue)]) -> Result<(), ParamError> {
+        let mut state = self.state.lock().unwrap();
+        write_atomically(&self.path, |writer| {
+            writeln!(writer, "{}", format_snapshot(params)).map_err(|err| io_error(&self.path, err))
+        })?;
+        // the old handle still points at the replaced file
+        state.file = OpenOptions::new().append(true).open(&self.path).map_err(|err| io_error(&self.path, err))?;
+        state.records = 0;
+        Ok(())
+    }
+
+    pub(crate) fn take_error(&self) -> Result<(), ParamError> {
+        match self.state.lock().unwrap().last_error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ParameterManager {
+    // Replays the journal at path into the manager, then appends every accepted change to it.
+    // Replayed values bypass rules and listeners see them as changes. Returns the number of
+    // records replayed, a snapshot counting as one.
+    pub fn open_journal<P: AsRef<Path>>(&mut self, path: P, options: JournalOptions) -> Result<usize, Vec<ParamError>> {
+        self.close_journal().map_err(|err| vec![err])?;
+        let path = path.as_ref().to_path_buf();
+        let contents = read_contents(&path)?;
+        let count = contents.lines.len();
+        let mut records = 0;
+        for line in contents.lines {
+            let values = match line {
+                JournalLine::Snapshot(values) => values.into_iter().map(|(key, value)| (key, Some(value))).collect(),
+                JournalLine::Change(record) => {
+                    records += 1;
+                    vec![(record.key, record.new)]
+                }
+            };
+            let values = values.into_iter().map(|(key, value)| {
+                // JSON does not tell small UInts from Ints, the rule does
+                let value = value.map(|value| match self.rule_for(&key).and_then(|rule| value.coerce_to(rule.param_type)) {
+                    Some(coerced) => coerced,
+                    None => value,
+                });
+                (key, value)
+            });
+            self.replay(values.collect());
+        }
+        self.journal = Some(Arc::new(Journal::open(path, options, contents.valid_len, records).map_err(|err| vec![err])?));
+        Ok(count)
+    }
+
+    // Reports the first write error since the last call, if any.
+    pub fn close_journal(&mut self) -> Result<(), ParamError> {
+        match self.journal.take() {
+            Some(journal) => journal.take_error(),
+            None => Ok(()),
+        }
+    }
+
+    // Tags the changes recorded from now on, e.g. with the name of the component making them.
+    pub fn set_journal_tag(&mut self, tag: Option<&str>) {
+        self.journal_tag = tag.map(str::to_string);
+    }
+
+    // Rewrites the journal as a single snapshot of the current values.
+    pub fn compact_journal(&self) -> Result<(), ParamError> {
+        match &self.journal {
+            Some(journal) => journal.compact(&self.sorted_params()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn journal_change(&self, key: &str, old: Option<&ParamValue>, new: Option<&ParamValue>) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let record = JournalRecord {
+            key: key.to_string(),
+            old: old.cloned(),
+            new: new.cloned(),
+            timestamp: SystemTime::now(),
+            tag: self.journal_tag.clone(),
+        };
+        if journal.append(&record) {
+            let params = self.sorted_params();
+            if let Err(err) = journal.compact(&params) {
+                journal.state.lock().unwrap().last_error = Some(err);
+            }
+        }
+    }
+
+    fn sorted_params(&self) -> Vec<(&String, &ParamValue)> {
+        let mut params: Vec<(&String, &ParamValue)> = self.params.iter().collect();
+        params.sort_by(|a, b| a.0.cmp(b.0));
+        params
+    }
+}
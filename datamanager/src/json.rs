@@ -0,0 +1,436 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// JSON document layout:
+//   {
+//     "params": { "audio": { "volume": 10, "codec": { "name": "aac" } } },
+//     "rules": { "audio.volume": { "type": "Int", "range": "Ranged", "min": 0, "max": 100 } }
+//   }
+// "params" nests the dot-separated key hierarchy. When a key is both a value and a parent
+// (e.g. "audio" and "audio.volume"), the value of the parent is stored under the "" member.
+// An empty segment (e.g. in "audio." or "a..b") is written as the "." member, which no other
+// segment can be.
+// "rules" is optional and keyed by the full key.
+
+use std::io::{Read, Write};
+
+use regex::Regex;
+use serde_json::{Map, Number, Value};
+
+use crate::{ParamError, ParamMetadata, ParamRange, ParamRule, ParamType, ParamValue, ParameterManager, SetOutcome, Visibility};
+
+const SELF_VALUE_KEY: &str = "";
+const EMPTY_SEGMENT_KEY: &str = ".";
+
+fn json_error(message: String) -> ParamError {
+    ParamError::Parse { line: 0, message }
+}
+
+pub(crate) fn value_to_json(value: &ParamValue) -> Value {
+    match value {
+        ParamValue::Int(val) => Value::from(*val),
+        ParamValue::UInt(val) => Value::from(*val),
+        // JSON has no NaN or infinity, keep them as text so they are not silently lost
+        ParamValue::Float(val) => Number::from_f64(*val).map(Value::Number).unwrap_or_else(|| Value::from(val.to_string())),
+        ParamValue::Bool(val) => Value::from(*val),
+        ParamValue::String(val) => Value::from(val.as_str()),
+    }
+}
+
+pub(crate) fn json_to_value(key: &str, value: &Value) -> Result<ParamValue, ParamError> {
+    match value {
+        Value::Bool(val) => Ok(ParamValue::Bool(*val)),
+        Value::String(val) => Ok(ParamValue::String(val.clone())),
+        Value::Number(val) => {
+            if let Some(val) = val.as_i64() {
+                Ok(ParamValue::Int(val))
+            } else if let Some(val) = val.as_u64() {
+                Ok(ParamValue::UInt(val))
+            } else {
+                val.as_f64().map(ParamValue::Float).ok_or_else(|| json_error(format!("{}: unsupported number {}", key, val)))
+            }
+        }
+        _ => Err(json_error(format!("{}: unsupported value {}", key, value))),
+    }
+}
+
+fn insert_nested(root: &mut Map<String, Value>, key: &str, value: Value) {
+    let mut node = root;
+    let mut segments = key.split('.').map(|segment| if segment.is_empty() { EMPTY_SEGMENT_KEY } else { segment }).peekable();
+    while let Some(segment) = segments.next() {
+        if segments.peek().is_none() {
+            match node.get_mut(segment) {
+                Some(Value::Object(children)) => {
+                    children.insert(SELF_VALUE_KEY.to_string(), value);
+                }
+                _ => {
+                    node.insert(segment.to_string(), value);
+                }
+            }
+            return;
+        }
+        let child = node.entry(segment.to_string()).or_insert_with(|| Value::Object(Map::new()));
+        if !child.is_object() {
+            // this segment already holds a value, move it under the "" member
+            let self_value = child.take();
+            let mut children = Map::new();
+            children.insert(SELF_VALUE_KEY.to_string(), self_value);
+            *child = Value::Object(children);
+        }
+        let Value::Object(children) = child else { unreachable!() };
+        node = children;
+    }
+}
+
+// prefix is None at the root, which is not the same as the empty key
+fn flatten_nested(prefix: Option<&str>, node: &Map<String, Value>, result: &mut Vec<(String, ParamValue)>) -> Result<(), ParamError> {
+    for (segment, child) in node {
+        let key = match (prefix, segment.as_str()) {
+            (Some(prefix), SELF_VALUE_KEY) => prefix.to_string(),
+            (Some(prefix), EMPTY_SEGMENT_KEY) => format!("{}.", prefix),
+            (Some(prefix), segment) => format!("{}.{}", prefix, segment),
+            (None, EMPTY_SEGMENT_KEY) => String::new(),
+            (None, segment) => segment.to_string(),
+        };
+        match child {
+            Value::Object(children) => flatten_nested(Some(&key), children, result)?,
+            _ => result.push((key.clone(), json_to_value(&key, child)?)),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn rule_to_json(rule: &ParamRule) -> Value {
+    let mut result = Map::new();
+    result.insert("type".to_string(), Value::from(rule.param_type.name()));
+    match rule.range {
+        ParamRange::RangeAny => {
+            result.insert("range".to_string(), Value::from("Any"));
+        }
+        ParamRange::Ranged => {
+            result.insert("range".to_string(), Value::from("Ranged"));
+            result.insert("min".to_string(), value_to_json(&ParamValue::Float(rule.range_min)));
+            result.insert("max".to_string(), value_to_json(&ParamValue::Float(rule.range_max)));
+        }
+        ParamRange::RangedInt { min, max } => {
+            result.insert("range".to_string(), Value::from("Ranged"));
+            result.insert("min".to_string(), Value::from(min));
+            result.insert("max".to_string(), Value::from(max));
+        }
+        ParamRange::RangedUInt { min, max } => {
+            result.insert("range".to_string(), Value::from("Ranged"));
+            result.insert("min".to_string(), Value::from(min));
+            result.insert("max".to_string(), Value::from(max));
+        }
+        ParamRange::RangeEnum => {
+            let mut enum_vals: Vec<&String> = rule.enum_vals.iter().collect();
+            enum_vals.sort();
+            result.insert("range".to_string(), Value::from("Enum"));
+            result.insert("enum".to_string(), Value::from(enum_vals.into_iter().map(|v| v.as_str()).collect::<Vec<_>>()));
+        }
+    }
+    if let Some(pattern) = &rule.pattern {
+        result.insert("pattern".to_string(), Value::from(pattern.as_str()));
+    }
+    if let Some(min_length) = rule.min_length {
+        result.insert("min_length".to_string(), Value::from(min_length));
+    }
+    if let Some(max_length) = rule.max_length {
+        result.insert("max_length".to_string(), Value::from(max_length));
+    }
+    if let Some(step) = rule.step {
+        result.insert("step".to_string(), value_to_json(&ParamValue::Float(step)));
+    }
+    if let Some(default_value) = &rule.default_value {
+        result.insert("default".to_string(), value_to_json(default_value));
+    }
+    if !rule.description.is_empty() {
+        result.insert("description".to_string(), Value::from(rule.description.as_str()));
+    }
+    if rule.read_only {
+        result.insert("read_only".to_string(), Value::from(true));
+    }
+    Value::Object(result)
+}
+
+// "range" may be omitted: "min"/"max" imply Ranged and "enum" implies Enum.
+pub(crate) fn json_to_rule(key: &str, value: &Value) -> Result<ParamRule, ParamError> {
+    let error = |message: &str| json_error(format!("rule of {}: {}", key, message));
+    if !value.is_object() {
+        return Err(error("must be an object"));
+    }
+    let param_type = value
+        .get("type")
+        .and_then(Value::as_str)
+        .and_then(ParamType::from_name)
+        .ok_or_else(|| error("missing or unknown type"))?;
+    let bound = |name: &str| value.get(name).ok_or_else(|| error(&format!("missing {}", name)));
+
+    let mut rule = ParamRule {
+        param_type,
+        ..Default::default()
+    };
+    let implied_range = if value.get("enum").is_some() {
+        "Enum"
+    } else if value.get("min").is_some() || value.get("max").is_some() {
+        "Ranged"
+    } else {
+        "Any"
+    };
+    match value.get("range").and_then(Value::as_str).unwrap_or(implied_range) {
+        "Any" => {}
+        // integer bounds of integer rules are kept exact
+        "Ranged" => {
+            let (min, max) = (bound("min")?, bound("max")?);
+            rule.range = match (param_type, min.as_i64(), max.as_i64(), min.as_u64(), max.as_u64()) {
+                (ParamType::TypeInt, Some(min), Some(max), _, _) => ParamRange::RangedInt { min, max },
+                (ParamType::TypeUInt, _, _, Some(min), Some(max)) => ParamRange::RangedUInt { min, max },
+                _ => {
+                    rule.range_min = min.as_f64().ok_or_else(|| error("min must be a number"))?;
+                    rule.range_max = max.as_f64().ok_or_else(|| error("max must be a number"))?;
+                    ParamRange::Ranged
+                }
+            };
+            let empty = match rule.range {
+                ParamRange::RangedInt { min, max } => min > max,
+                ParamRange::RangedUInt { min, max } => min > max,
+                _ => rule.range_min > rule.range_max,
+            };
+            if empty {
+                return Err(error("min greater than max"));
+            }
+        }
+        "Enum" => {
+            rule.range = ParamRange::RangeEnum;
+            rule.enum_vals = value
+                .get("enum")
+                .and_then(Value::as_array)
+                .ok_or_else(|| error("missing enum"))?
+                .iter()
+                .map(|v| v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()))
+                .collect();
+        }
+        other => return Err(error(&format!("unknown range {}", other))),
+    }
+    if let Some(pattern) = value.get("pattern") {
+        let pattern = pattern.as_str().ok_or_else(|| error("pattern must be a string"))?;
+        rule.pattern = Some(Regex::new(pattern).map_err(|err| error(&err.to_string()))?);
+    }
+    let length = |name: &str| match value.get(name) {
+        Some(length) => length.as_u64().map(|length| Some(length as usize)).ok_or_else(|| error(&format!("{} must be a positive integer", name))),
+        None => Ok(None),
+    };
+    rule.min_length = length("min_length")?;
+    rule.max_length = length("max_length")?;
+    if let Some(step) = value.get("step") {
+        rule.step = Some(step.as_f64().filter(|step| *step > 0.0).ok_or_else(|| error("step must be a positive number"))?);
+    }
+    if let Some(default_value) = value.get("default") {
+        let default_value = json_to_value(key, default_value)?
+            .coerce_to(param_type)
+            .ok_or_else(|| error("default does not match the type"))?;
+        rule.default_value = Some(default_value);
+    }
+    if let Some(description) = value.get("description") {
+        rule.description = description.as_str().ok_or_else(|| error("description must be a string"))?.to_string();
+    }
+    if let Some(read_only) = value.get("read_only") {
+        rule.read_only = read_only.as_bool().ok_or_else(|| error("read_only must be a bool"))?;
+    }
+    Ok(rule)
+}
+
+// Adds the metadata fields to a rule entry. The description belongs to the rule.
+pub(crate) fn metadata_to_json(metadata: &ParamMetadata, entry: &mut Map<String, Value>) {
+    if let Some(display_name) = &metadata.display_name {
+        entry.insert("display_name".to_string(), Value::from(display_name.as_str()));
+    }
+    if let Some(unit) = &metadata.unit {
+        entry.insert("unit".to_string(), Value::from(unit.as_str()));
+    }
+    if !metadata.tags.is_empty() {
+        entry.insert("tags".to_string(), Value::from(metadata.tags.clone()));
+    }
+    if metadata.visibility != Visibility::User {
+        entry.insert("visibility".to_string(), Value::from(metadata.visibility.name()));
+    }
+    if let Some(deprecated) = &metadata.deprecated {
+        entry.insert("deprecated".to_string(), Value::from(deprecated.as_str()));
+    }
+}
+
+// None when the entry has no metadata field.
+pub(crate) fn json_to_metadata(key: &str, value: &Value) -> Result<Option<ParamMetadata>, ParamError> {
+    let error = |message: &str| json_error(format!("metadata of {}: {}", key, message));
+    let text = |name: &str| match value.get(name) {
+        Some(text) => text.as_str().map(|text| Some(text.to_string())).ok_or_else(|| error(&format!("{} must be a string", name))),
+        None => Ok(None),
+    };
+    let mut metadata = ParamMetadata {
+        display_name: text("display_name")?,
+        unit: text("unit")?,
+        deprecated: text("deprecated")?,
+        ..Default::default()
+    };
+    if let Some(tags) = value.get("tags") {
+        metadata.tags = tags
+            .as_array()
+            .and_then(|tags| tags.iter().map(|tag| tag.as_str().map(str::to_string)).collect())
+            .ok_or_else(|| error("tags must be an array of strings"))?;
+    }
+    if let Some(visibility) = value.get("visibility") {
+        metadata.visibility = visibility.as_str().and_then(Visibility::from_name).ok_or_else(|| error("unknown visibility"))?;
+    }
+    Ok(if metadata == ParamMetadata::default() { None } else { Some(metadata) })
+}
+
+pub(crate) struct JsonDocument {
+    pub rules: Vec<(String, ParamRule)>,
+    pub metadata: Vec<(String, ParamMetadata)>,
+    pub values: Vec<(String, ParamValue)>,
+}
+
+pub(crate) fn parse_json_document<R: Read>(reader: &mut R) -> Result<JsonDocument, ParamError> {
+    let document: Value = serde_json::from_reader(reader).map_err(|err| ParamError::Parse {
+        line: err.line(),
+        message: err.to_string(),
+    })?;
+
+    let mut rules = Vec::new();
+    let mut metadata = Vec::new();
+    if let Some(rule_values) = document.get("rules") {
+        let rule_values = rule_values.as_object().ok_or_else(|| json_error("rules must be an object".to_string()))?;
+        for (key, rule) in rule_values {
+            rules.push((key.clone(), json_to_rule(key, rule)?));
+            if let Some(entry) = json_to_metadata(key, rule)? {
+                metadata.push((key.clone(), entry));
+            }
+        }
+    }
+
+    let mut values = Vec::new();
+    match document.get("params") {
+        Some(Value::Object(params)) => flatten_nested(None, params, &mut values)?,
+        Some(_) => return Err(json_error("params must be an object".to_string())),
+        None => {}
+    }
+    Ok(JsonDocument { rules, metadata, values })
+}
+
+impl ParameterManager {
+    pub fn to_json(&self, include_rules: bool) -> String {
+        let mut params = Map::new();
+        let mut keys: Vec<&String> = self.params.keys().filter(|key| !self.is_hidden(key)).collect();
+        keys.sort();
+        for key in keys {
+            insert_nested(&mut params, key, value_to_json(&self.params[key]));
+        }
+
+        let mut document = Map::new();
+        document.insert("params".to_string(), Value::Object(params));
+        if include_rules {
+            let wild_card_rules = self.wild_card_rules.iter().map(|(prefix, rule)| (format!("{}*", prefix), rule));
+            let rules: Map<String, Value> = self
+                .param_rules
+                .iter()
+                .map(|(key, rule)| (key.clone(), rule))
+                .chain(wild_card_rules)
+                .map(|(key, rule)| {
+                    let mut entry = rule_to_json(rule);
+                    let metadata = match key.strip_suffix('*') {
+                        Some(prefix) => self.wild_card_metadata.get(prefix),
+                        None => self.metadata.get(&key),
+                    };
+                    if let (Some(metadata), Value::Object(entry)) = (metadata, &mut entry) {
+                        metadata_to_json(metadata, entry);
+                    }
+                    (key, entry)
+                })
+                .collect();
+            document.insert("rules".to_string(), Value::Object(rules));
+        }
+        serde_json::to_string_pretty(&Value::Object(document)).unwrap()
+    }
+
+    pub fn store_to_json<W: Write + ?Sized>(&self, writer: &mut W, include_rules: bool) -> Result<(), ParamError> {
+        writer
+            .write_all(self.to_json(include_rules).as_bytes())
+            .and_then(|_| writer.write_all(b"\n"))
+            .map_err(|err| ParamError::Io(err.to_string()))
+    }
+
+    // All-or-nothing like restore_from_typed_stream: the values are checked against the rules of
+    // the document, and nothing is applied, not even a rule, when one of them is refused.
+    // Returns the number of values stored.
+    pub fn restore_from_json<R: Read>(&mut self, reader: &mut R, override_existing: bool) -> Result<usize, Vec<ParamError>> {
+        let document = parse_json_document(reader).map_err(|err| vec![err])?;
+
+        // in place only for the check, installed for real once the values pass
+        let replaced: Vec<(String, Option<ParamRule>)> = document
+            .rules
+            .iter()
+            .map(|(pattern, rule)| (pattern.clone(), self.swap_rule(pattern, Some(rule.clone()))))
+            .collect();
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for (key, value) in document.values {
+            if !override_existing && self.params.contains_key(&key) {
+                continue;
+            }
+            match self.check_change(&key, value.clone()) {
+                Ok((_, checked)) if self.params.get(&key) == Some(&checked) => {}
+                Ok((_, checked)) => match self.check_access(&key, None) {
+                    Some(SetOutcome::Denied) => errors.push(ParamError::Denied { key }),
+                    Some(_) => errors.push(ParamError::ReadOnly { key }),
+                    None => entries.push((key, checked)),
+                },
+                Err(reason) => errors.push(ParamError::Rejected { key, value, reason }),
+            }
+        }
+        for (pattern, rule) in replaced.into_iter().rev() {
+            self.swap_rule(&pattern, rule);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for (key, rule) in document.rules {
+            self.set_parameter_rule(&key, rule);
+        }
+        for (key, metadata) in document.metadata {
+            self.set_parameter_metadata(&key, metadata);
+        }
+        let count = entries.len();
+        self.history.begin_group();
+        for (key, value) in entries {
+            self.store_value(&key, value);
+        }
+        self.history.end_group();
+        Ok(count)
+    }
+
+    // Installs or removes a rule without revalidating the stored values, returns the replaced one.
+    fn swap_rule(&mut self, pattern: &str, rule: Option<ParamRule>) -> Option<ParamRule> {
+        let (rules, name) = match pattern.strip_suffix('*') {
+            Some(prefix) => (&mut self.wild_card_rules, prefix),
+            None => (&mut self.param_rules, pattern),
+        };
+        match rule {
+            Some(rule) => rules.insert(name.to_string(), rule),
+            None => rules.remove(name),
+        }
+    }
+}
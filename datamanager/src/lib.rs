@@ -16,19 +16,78 @@
 
 use std::str::FromStr;
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
 use once_cell::sync::Lazy;
+use regex::Regex;
 use std::io::{BufRead, Write};
 
-
-#[derive(Clone)]
+mod access;
+mod channel;
+mod defaults;
+mod dispatch;
+mod history;
+#[cfg(unix)]
+mod ipc;
+mod journal;
+mod json;
+mod listeners;
+mod metadata;
+mod persist;
+mod reload;
+mod schema;
+mod transaction;
+mod tree;
+mod typed_text;
+mod veto;
+
+pub use access::{AccessMode, AccessPolicy};
+#[cfg(feature = "tokio")]
+pub use channel::ChangeStream;
+pub use channel::{ChangeReceiver, OverflowPolicy, ParamChange, SubscribeOptions};
+pub use dispatch::{DispatchExecutor, DispatchJob, DispatchMode};
+pub use history::{ParamDiff, RestoreReport};
+#[cfg(unix)]
+pub use ipc::{ParamClient, ParamServer};
+pub use journal::{read_journal, JournalOptions, JournalRecord};
+pub use metadata::{ParamInfo, ParamMetadata, Visibility};
+pub use persist::{PersistOptions, PersistPolicy};
+pub use reload::{FileWatcher, ReloadReport};
+pub use schema::{Schema, SchemaViolation};
+pub use transaction::Transaction;
+pub use veto::{ChangeDecision, VetoCallback};
+use dispatch::Dispatcher;
+use history::History;
+use journal::Journal;
+use listeners::ListenerIndex;
+use persist::FilePersistence;
+use veto::VetoListener;
+
+
+const DEFAULT_MANAGER_NAME: &str = "";
+
+static MANAGER_INSTANCES: Lazy<Mutex<HashMap<String, Arc<Mutex<ParameterManager>>>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+#[derive(Clone, Default)]
 pub struct ParameterManager {
-    params: HashMap<String, String>,
+    params: HashMap<String, ParamValue>,
     param_rules: HashMap<String, ParamRule>,
-    listeners: HashMap<String, Vec<Listener>>,
-    wild_card_listeners: HashMap<String, Vec<Listener>>,
-    listener_id_reverse: HashMap<usize, String>,
+    wild_card_rules: HashMap<String, ParamRule>,
+    access_policies: HashMap<String, AccessPolicy>,
+    wild_card_access_policies: HashMap<String, AccessPolicy>,
+    metadata: HashMap<String, ParamMetadata>,
+    wild_card_metadata: HashMap<String, ParamMetadata>,
+    listeners: ListenerIndex<Listener>,
+    veto_listeners: ListenerIndex<VetoListener>,
+    batch_listeners: Vec<BatchListener>,
     listener_id: usize,
+    persistence: Option<Arc<FilePersistence>>,
+    dispatcher: Option<Arc<Dispatcher>>,
+    history: History,
+    journal: Option<Arc<Journal>>,
+    journal_tag: Option<String>,
 }
 
 #[derive(Clone)]
@@ -37,28 +96,116 @@ pub struct Param {
     pub value: String,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ParamType {
     TypeInt,
+    TypeUInt,
     TypeFloat,
     TypeBool,
     TypeString,
 }
 
-#[derive(Clone)]
+// Natively typed parameter value. New variants may be added, so match with a wildcard arm.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ParamValue {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamError {
+    TypeMismatch { key: String, expected: ParamType, actual: ParamType },
+    OutOfRange { key: String, value: ParamValue },
+    Parse { line: usize, message: String },
+    Io(String),
+    Rejected { key: String, value: ParamValue, reason: RejectReason },
+    ReadOnly { key: String },
+    Denied { key: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetOutcome {
+    Stored,
+    Unchanged,
+    Clamped { from: ParamValue, to: ParamValue },
+    Rejected { reason: RejectReason },
+    ReadOnly,
+    // the key's access policy names an owner and the caller did not present its token
+    Denied,
+}
+
+// What installing a rule did to a value stored before it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Revalidation {
+    // converted to the rule's type, clamped or quantized
+    Adjusted { key: String, from: ParamValue, to: ParamValue },
+    // broke the rule and was replaced by the rule's default
+    Reset { key: String, from: ParamValue, to: ParamValue, reason: RejectReason },
+    // broke the rule and is kept because the rule has no default
+    Invalid { key: String, value: ParamValue, reason: RejectReason },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum RejectReason {
+    TypeMismatch { expected: ParamType, actual: ParamType },
+    NotInEnum { allowed: Vec<String> },
+    PatternMismatch { pattern: String },
+    TooShort { min_length: usize },
+    TooLong { max_length: usize },
+    // message returned by a custom validator
+    Invalid(String),
+    // message returned by a veto listener
+    Vetoed(String),
+    // the value would have to change, but its access policy refuses an anonymous write
+    Protected,
+    // the rule's range holds no value of its type, e.g. Int on [0.5, 0.7] or a NaN bound
+    EmptyRange { range: String },
+}
+
+// Custom constraint; Err carries the message shown to the user.
+pub type ParamValidator = Arc<dyn Fn(&ParamValue) -> Result<(), String> + Send + Sync>;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ParamRange {
     RangeAny,
+    // range_min..=range_max as f64, exact for integers up to 2^53
     Ranged,
     RangeEnum,
+    // exact 64-bit integer bounds
+    RangedInt { min: i64, max: i64 },
+    RangedUInt { min: u64, max: u64 },
 }
 
 #[derive(Clone)]
 pub struct ParamRule {
     pub param_type: ParamType,
     pub range: ParamRange,
-    pub range_min: f32,
-    pub range_max: f32,
+    pub range_min: f64,
+    pub range_max: f64,
     pub enum_vals: HashSet<String>,
+    // All the constraints below compose with the range; each one that is set must hold.
+    // Like Regex::is_match the pattern is not anchored, use ^...$ to match the whole value.
+    pub pattern: Option<Regex>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    // numeric values are rounded to range_min (or 0) + n * step
+    pub step: Option<f64>,
+    pub validators: Vec<ParamValidator>,
+    pub default_value: Option<ParamValue>,
+    pub description: String,
+    // write-once like the "ro." prefix
+    pub read_only: bool,
+}
+
+// Unregisters its listener on drop. Do not drop it while holding the manager's lock.
+pub struct Subscription {
+    manager: Weak<Mutex<ParameterManager>>,
+    listener_id: Option<usize>,
 }
 
 #[derive(Clone)]
@@ -67,127 +214,577 @@ pub struct Listener {
     pub callback: Arc<Mutex<dyn Fn(String, String) + Send + Sync>>,
 }
 
-impl ParameterManager {
-    pub fn new() -> Self {
-        ParameterManager {
-            params: HashMap::new(),
-            param_rules: HashMap::new(),
-            listeners: HashMap::new(),
-            wild_card_listeners: HashMap::new(),
-            listener_id_reverse: HashMap::new(),
-            listener_id: 0,
+pub type BatchCallback = Arc<Mutex<dyn Fn(Vec<(String, ParamValue)>) + Send + Sync>>;
+
+// Receives every change committed by a transaction at once.
+#[derive(Clone)]
+pub struct BatchListener {
+    pub listener_id: usize,
+    pub callback: BatchCallback,
+}
+
+// The exact rule wins, otherwise the wild card rule with the longest matching prefix.
+pub(crate) fn lookup_pattern<'a, T>(exact: &'a HashMap<String, T>, wild_card: &'a HashMap<String, T>, key: &str) -> Option<&'a T> {
+    exact.get(key).or_else(|| {
+        wild_card
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, entry)| entry)
+    })
+}
+
+impl Default for ParamRule {
+    fn default() -> Self {
+        ParamRule {
+            param_type: ParamType::TypeString,
+            range: ParamRange::RangeAny,
+            range_min: 0.0,
+            range_max: 0.0,
+            enum_vals: HashSet::new(),
+            pattern: None,
+            min_length: None,
+            max_length: None,
+            step: None,
+            validators: Vec::new(),
+            default_value: None,
+            description: String::new(),
+            read_only: false,
         }
     }
+}
 
-    pub fn get_manager() -> Arc<Mutex<ParameterManager>> {
-        static INSTANCE: Lazy<Arc<Mutex<Option<ParameterManager>>>> = Lazy::new(|| {
-            Arc::new(Mutex::new(None))
-        });
+impl ParamRule {
+    // Rounds numeric values to the nearest step counted from the lower bound (or 0),
+    // keeping them inside the range. Integers with an integral step are rounded exactly.
+    pub fn quantize(&self, value: &mut ParamValue) {
+        let Some(step) = self.step.filter(|step| *step > 0.0) else {
+            return;
+        };
+        match value {
+            // a step past u64::MAX is as coarse as the float path, which cannot overflow
+            ParamValue::Int(_) | ParamValue::UInt(_) if step.fract() == 0.0 && step <= u64::MAX as f64 => {
+                let base = self.int_bounds().map_or(0, |(min, _)| min);
+                let step = step as i128;
+                // out of reach only with saturated bounds, the value is then left to clamp
+                let quantize = |val: i128| {
+                    let rounded = val.checked_sub(base)?.checked_mul(2)?.checked_add(step)?.div_euclid(step * 2);
+                    base.checked_add(rounded.checked_mul(step)?)
+                };
+                update_int(value, |val| quantize(val).unwrap_or(val));
+            }
+            ParamValue::Int(_) | ParamValue::UInt(_) | ParamValue::Float(_) => {
+                let base = self.float_bounds().map_or(0.0, |(min, _)| min);
+                let quantized = value.as_f64().map(|val| base + ((val - base) / step).round() * step);
+                match value {
+                    ParamValue::Float(val) => *val = quantized.unwrap_or(*val),
+                    _ => update_int(value, |val| quantized.map_or(val, |q| q as i128)),
+                }
+            }
+            _ => return,
+        }
+        // an empty range leaves the value as is, clamp reports it
+        let _ = self.clamp(value);
+    }
 
-        let mut instance = INSTANCE.lock().unwrap();
-        if instance.is_none() {
-            *instance = Some(ParameterManager::new());
+    // Err when no value of the type fits the range, the value is then left unchanged.
+    pub fn clamp(&self, value: &mut ParamValue) -> Result<(), RejectReason> {
+        let empty = || RejectReason::EmptyRange {
+            range: self.range_text().unwrap_or_default(),
+        };
+        match value {
+            ParamValue::Float(val) => {
+                if let Some((min, max)) = self.float_bounds() {
+                    if min.is_nan() || max.is_nan() || min > max {
+                        return Err(empty());
+                    }
+                    *val = val.clamp(min, max);
+                }
+            }
+            ParamValue::Int(_) | ParamValue::UInt(_) => {
+                if let Some((min, max)) = self.int_bounds() {
+                    if min > max || self.range_min.is_nan() || self.range_max.is_nan() {
+                        return Err(empty());
+                    }
+                    update_int(value, |val| val.clamp(min, max));
+                }
+            }
+            _ => {}
         }
+        Ok(())
+    }
 
-        Arc::clone(&Arc::new(Mutex::new(instance.take().unwrap())))
+    pub fn range_text(&self) -> Option<String> {
+        match self.range {
+            ParamRange::Ranged => Some(format!("[{}, {}]", self.range_min, self.range_max)),
+            ParamRange::RangedInt { min, max } => Some(format!("[{}, {}]", min, max)),
+            ParamRange::RangedUInt { min, max } => Some(format!("[{}, {}]", min, max)),
+            _ => None,
+        }
     }
 
-    pub fn set_parameter<T: ToString>(&mut self, key: &str, value: T) {
-        let mut value = value.to_string().trim().to_string();
-        if self.filter_value_with_rule(&key, &mut value) {
-            let mut b_changed = true;
+    fn int_bounds(&self) -> Option<(i128, i128)> {
+        match self.range {
+            ParamRange::Ranged => Some((self.range_min.ceil() as i128, self.range_max.floor() as i128)),
+            ParamRange::RangedInt { min, max } => Some((min as i128, max as i128)),
+            ParamRange::RangedUInt { min, max } => Some((min as i128, max as i128)),
+            _ => None,
+        }
+    }
 
-            if self.params.contains_key(key) {
-                // Read-only key check (similar to "ro." check
-                if key.starts_with("ro.") {
-                    return;
-                }
+    fn float_bounds(&self) -> Option<(f64, f64)> {
+        match self.range {
+            ParamRange::Ranged => Some((self.range_min, self.range_max)),
+            ParamRange::RangedInt { min, max } => Some((min as f64, max as f64)),
+            ParamRange::RangedUInt { min, max } => Some((min as f64, max as f64)),
+            _ => None,
+        }
+    }
 
-                b_changed = self.params[key] != value;
+    // Checks the constraints that clamping cannot fix: enum, pattern, length and custom validators.
+    pub fn check_constraints(&self, value: &ParamValue) -> Result<(), RejectReason> {
+        let text = value.to_string();
+        if let ParamRange::RangeEnum = self.range
+            && !self.enum_vals.contains(&text)
+        {
+            let mut allowed: Vec<String> = self.enum_vals.iter().cloned().collect();
+            allowed.sort();
+            return Err(RejectReason::NotInEnum { allowed });
+        }
+        if let Some(pattern) = &self.pattern
+            && !pattern.is_match(&text)
+        {
+            return Err(RejectReason::PatternMismatch { pattern: pattern.as_str().to_string() });
+        }
+        let length = text.chars().count();
+        if let Some(min_length) = self.min_length.filter(|min_length| length < *min_length) {
+            return Err(RejectReason::TooShort { min_length });
+        }
+        if let Some(max_length) = self.max_length.filter(|max_length| length > *max_length) {
+            return Err(RejectReason::TooLong { max_length });
+        }
+        for validator in &self.validators {
+            validator(value).map_err(RejectReason::Invalid)?;
+        }
+        Ok(())
+    }
+}
+
+// Applies f to an Int or UInt value without losing precision, saturating at the bounds of its type.
+fn update_int<F: Fn(i128) -> i128>(value: &mut ParamValue, f: F) {
+    match value {
+        ParamValue::Int(val) => *val = f(*val as i128).clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+        ParamValue::UInt(val) => *val = f(*val as i128).clamp(0, u64::MAX as i128) as u64,
+        _ => {}
+    }
+}
+
+impl ParamType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamType::TypeInt => "Int",
+            ParamType::TypeUInt => "UInt",
+            ParamType::TypeFloat => "Float",
+            ParamType::TypeBool => "Bool",
+            ParamType::TypeString => "String",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ParamType> {
+        match name {
+            "Int" => Some(ParamType::TypeInt),
+            "UInt" => Some(ParamType::TypeUInt),
+            "Float" => Some(ParamType::TypeFloat),
+            "Bool" => Some(ParamType::TypeBool),
+            "String" => Some(ParamType::TypeString),
+            _ => None,
+        }
+    }
+}
+
+impl ParamValue {
+    pub fn param_type(&self) -> ParamType {
+        match self {
+            ParamValue::Int(_) => ParamType::TypeInt,
+            ParamValue::UInt(_) => ParamType::TypeUInt,
+            ParamValue::Float(_) => ParamType::TypeFloat,
+            ParamValue::Bool(_) => ParamType::TypeBool,
+            ParamValue::String(_) => ParamType::TypeString,
+        }
+    }
+
+    // Lossless conversion only: Float(3.9) and "3.9" are not integers.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ParamValue::Int(val) => Some(*val),
+            ParamValue::UInt(val) => i64::try_from(*val).ok(),
+            ParamValue::Float(val) => Self::float_to_i128(*val).and_then(|val| i64::try_from(val).ok()),
+            ParamValue::String(val) => {
+                let val = val.trim();
+                val.parse::<i64>().ok().or_else(|| {
+                    val.parse::<f64>().ok().and_then(Self::float_to_i128).and_then(|val| i64::try_from(val).ok())
+                })
             }
+            _ => None,
+        }
+    }
 
-            self.params.insert(key.to_string(), value.clone());
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            ParamValue::Int(val) => u64::try_from(*val).ok(),
+            ParamValue::UInt(val) => Some(*val),
+            ParamValue::Float(val) => Self::float_to_i128(*val).and_then(|val| u64::try_from(val).ok()),
+            ParamValue::String(val) => {
+                let val = val.trim();
+                val.parse::<u64>().ok().or_else(|| {
+                    val.parse::<f64>().ok().and_then(Self::float_to_i128).and_then(|val| u64::try_from(val).ok())
+                })
+            }
+            _ => None,
+        }
+    }
 
-            if b_changed {
-                for (a_key, listeners) in &self.wild_card_listeners {
-                    if key.starts_with(a_key) {
-                        self.execute_notify(&key, &value, listeners.clone());
-                    }
-                }
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ParamValue::Int(val) => Some(*val as f64),
+            ParamValue::UInt(val) => Some(*val as f64),
+            ParamValue::Float(val) => Some(*val),
+            ParamValue::String(val) => val.trim().parse::<f64>().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ParamValue::Bool(val) => Some(*val),
+            ParamValue::String(val) => val.trim().parse::<bool>().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn coerce_to(&self, param_type: ParamType) -> Option<ParamValue> {
+        match param_type {
+            ParamType::TypeInt => self.as_i64().map(ParamValue::Int),
+            ParamType::TypeUInt => self.as_u64().map(ParamValue::UInt),
+            ParamType::TypeFloat => self.as_f64().map(ParamValue::Float),
+            ParamType::TypeBool => self.as_bool().map(ParamValue::Bool),
+            ParamType::TypeString => Some(ParamValue::String(self.to_string())),
+        }
+    }
+
+    // integral floats only; the caller narrows the result to the width it needs
+    fn float_to_i128(val: f64) -> Option<i128> {
+        if val.fract() == 0.0 && val >= i64::MIN as f64 && val < u64::MAX as f64 {
+            Some(val as i128)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::Int(val) => write!(f, "{}", val),
+            ParamValue::UInt(val) => write!(f, "{}", val),
+            ParamValue::Float(val) => write!(f, "{}", val),
+            ParamValue::Bool(val) => write!(f, "{}", val),
+            ParamValue::String(val) => write!(f, "{}", val),
+        }
+    }
+}
 
-                if let Some(listeners) = self.listeners.get(key) {
-                    self.execute_notify(&key, &value, listeners.clone());
+macro_rules! impl_param_value_from {
+    ($variant:ident, $target:ty, $($src:ty),+) => {
+        $(
+            impl From<$src> for ParamValue {
+                fn from(value: $src) -> Self {
+                    ParamValue::$variant(<$target>::from(value))
                 }
             }
+        )+
+    };
+}
+
+impl_param_value_from!(Int, i64, i8, i16, i32, i64, u8, u16, u32);
+impl_param_value_from!(UInt, u64, u64);
+impl_param_value_from!(Float, f64, f32, f64);
+
+impl From<usize> for ParamValue {
+    fn from(value: usize) -> Self {
+        ParamValue::UInt(value as u64)
+    }
+}
+
+impl From<bool> for ParamValue {
+    fn from(value: bool) -> Self {
+        ParamValue::Bool(value)
+    }
+}
+
+impl From<&str> for ParamValue {
+    fn from(value: &str) -> Self {
+        ParamValue::String(value.to_string())
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(value: String) -> Self {
+        ParamValue::String(value)
+    }
+}
+
+impl From<&String> for ParamValue {
+    fn from(value: &String) -> Self {
+        ParamValue::String(value.clone())
+    }
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::TypeMismatch { key, expected, actual } => {
+                write!(f, "{}: expected {:?} but the value is {:?}", key, expected, actual)
+            }
+            ParamError::OutOfRange { key, value } => {
+                write!(f, "{}: {} is out of range", key, value)
+            }
+            ParamError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ParamError::Io(message) => write!(f, "I/O error: {}", message),
+            ParamError::Rejected { key, value, reason } => write!(f, "{}: {} is rejected, {}", key, value, reason),
+            ParamError::ReadOnly { key } => write!(f, "{} is read-only", key),
+            ParamError::Denied { key } => write!(f, "{} may only be written by its owner", key),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl SetOutcome {
+    // true when the requested value, possibly clamped, is what the key now holds
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, SetOutcome::Stored | SetOutcome::Unchanged | SetOutcome::Clamped { .. })
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::TypeMismatch { expected, actual } => {
+                write!(f, "expected {} but got {}", expected.name(), actual.name())
+            }
+            RejectReason::NotInEnum { allowed } => write!(f, "not one of {}", allowed.join(", ")),
+            RejectReason::PatternMismatch { pattern } => write!(f, "does not match {}", pattern),
+            RejectReason::TooShort { min_length } => write!(f, "shorter than {} characters", min_length),
+            RejectReason::TooLong { max_length } => write!(f, "longer than {} characters", max_length),
+            RejectReason::Invalid(message) => write!(f, "{}", message),
+            RejectReason::Vetoed(message) => write!(f, "vetoed, {}", message),
+            RejectReason::Protected => write!(f, "protected by its access policy"),
+            RejectReason::EmptyRange { range } => write!(f, "outside the empty range {}", range),
+        }
+    }
+}
+
+impl Subscription {
+    pub fn listener_id(&self) -> Option<usize> {
+        self.listener_id
+    }
+
+    // Keeps the listener registered for the lifetime of the manager.
+    pub fn detach(mut self) -> Option<usize> {
+        self.listener_id.take()
+    }
+
+    pub fn unsubscribe(&mut self) -> bool {
+        match (self.listener_id.take(), self.manager.upgrade()) {
+            (Some(listener_id), Some(manager)) => manager.lock().unwrap().unregister_callback(listener_id),
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.unsubscribe();
+    }
+}
+
+impl ParameterManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_manager() -> Arc<Mutex<ParameterManager>> {
+        Self::get_manager_named(DEFAULT_MANAGER_NAME)
+    }
+
+    // Each name owns an isolated store shared by every caller asking for that name.
+    pub fn get_manager_named(name: &str) -> Arc<Mutex<ParameterManager>> {
+        let mut instances = MANAGER_INSTANCES.lock().unwrap();
+        Arc::clone(
+            instances
+                .entry(name.to_string())
+                .or_insert_with(|| Arc::new(Mutex::new(ParameterManager::new()))),
+        )
+    }
+
+    // Clears the shared store in place, so handles obtained earlier also see the clean store.
+    pub fn reset_manager() {
+        Self::reset_manager_named(DEFAULT_MANAGER_NAME);
+    }
+
+    pub fn reset_manager_named(name: &str) {
+        let instance = MANAGER_INSTANCES.lock().unwrap().get(name).cloned();
+        if let Some(instance) = instance {
+            let mut manager = instance.lock().unwrap();
+            // keep issuing fresh ids so stale Subscriptions cannot remove new listeners
+            let listener_id = manager.listener_id;
+            *manager = ParameterManager::new();
+            manager.listener_id = listener_id;
         }
     }
 
+    pub fn set_parameter<T: Into<ParamValue>>(&mut self, key: &str, value: T) {
+        self.try_set_parameter(key, value);
+    }
+
+    pub fn try_set_parameter<T: Into<ParamValue>>(&mut self, key: &str, value: T) -> SetOutcome {
+        self.try_set_parameter_with_token(key, value.into(), None)
+    }
+
+    pub(crate) fn try_set_parameter_with_token(&mut self, key: &str, value: ParamValue, token: Option<&str>) -> SetOutcome {
+        let (requested, value) = match self.check_change(key, value) {
+            Ok(checked) => checked,
+            Err(reason) => return SetOutcome::Rejected { reason },
+        };
+
+        if self.params.get(key) == Some(&value) {
+            return SetOutcome::Unchanged;
+        }
+        if let Some(denied) = self.check_access(key, token) {
+            return denied;
+        }
+
+        self.store_value(key, value.clone());
+
+        if requested == value {
+            SetOutcome::Stored
+        } else {
+            SetOutcome::Clamped { from: requested, to: value }
+        }
+    }
+
+    // Stores an already checked value, then persists it and notifies the listeners.
+    pub(crate) fn store_value(&mut self, key: &str, value: ParamValue) {
+        let value_str = value.to_string();
+        let before = self.params.insert(key.to_string(), value.clone());
+        self.journal_change(key, before.as_ref(), Some(&value));
+        self.history.record(key, before, Some(value));
+        self.schedule_persist(key);
+        self.notify(key, value_str);
+    }
+
+    pub(crate) fn notify(&self, key: &str, value: String) {
+        let listeners = self.listeners.matching(key);
+        match &self.dispatcher {
+            Some(dispatcher) => dispatcher.enqueue(key, value, listeners),
+            None => self.execute_notify(key, &value, listeners),
+        }
+    }
+
+    // key may be a pattern, see listeners.rs: "audio.volume", "audio.*", "audio.*.volume", "audio.**"
     pub fn register_callback<F>(&mut self, key: &str, callback: F) -> usize
     where
         F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let listener = self.new_listener(callback);
+        let listener_id = listener.listener_id;
+        self.listeners.insert(key, listener);
+        listener_id
+    }
+
+    // Called for every key the expression matches, anchors are up to the caller. Flags go inline,
+    // e.g. "(?i)^audio\\.vol".
+    pub fn register_regex_callback<F>(&mut self, pattern: &str, callback: F) -> Result<usize, regex::Error>
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let listener = self.new_listener(callback);
+        let listener_id = listener.listener_id;
+        self.listeners.insert_regex(pattern, listener)?;
+        Ok(listener_id)
+    }
+
+    // Transactions only; single sets reach the key listeners alone.
+    pub fn register_batch_callback<F>(&mut self, callback: F) -> usize
+    where
+        F: Fn(Vec<(String, ParamValue)>) + Send + Sync + 'static,
     {
         let listener_id = self.listener_id;
         self.listener_id += 1;
-
-        let listener = Listener {
+        self.batch_listeners.push(BatchListener {
             listener_id,
             callback: Arc::new(Mutex::new(callback)),
-        };
+        });
+        listener_id
+    }
 
-        if key.ends_with('*') {
-            // wild card case
-            let _key = key[..key.len() - 1].to_string();
-            self.wild_card_listeners
-                .entry(_key.clone())
-                .or_insert_with(Vec::new)
-                .push(listener.clone());
-            self.listener_id_reverse.insert(listener_id, key.to_string());
-        } else {
-            // complete match case
-            self.listeners
-                .entry(key.to_string())
-                .or_insert_with(Vec::new)
-                .push(listener.clone());
-            self.listener_id_reverse.insert(listener_id, key.to_string());
+    fn new_listener<F>(&mut self, callback: F) -> Listener
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let listener_id = self.listener_id;
+        self.listener_id += 1;
+        Listener {
+            listener_id,
+            callback: Arc::new(Mutex::new(callback)),
         }
+    }
 
-        listener_id
+    // Removes any kind of listener: key, batch or veto.
+    pub fn unregister_callback(&mut self, listener_id: usize) -> bool {
+        let batch_count = self.batch_listeners.len();
+        self.batch_listeners.retain(|listener| listener.listener_id != listener_id);
+        self.listeners.remove(listener_id) || self.veto_listeners.remove(listener_id) || batch_count != self.batch_listeners.len()
     }
 
-    pub fn filter_value_with_rule(&self, key: &str, value: &mut String) -> bool {
-        if let Some(rule) = self.param_rules.get(key) {
-            match rule.range {
-                ParamRange::RangeAny => {}
-                ParamRange::Ranged => match rule.param_type {
-                    ParamType::TypeInt => {
-                        if let Ok(val) = value.parse::<i32>() {
-                            let clamped_val = val.clamp(rule.range_min as i32, rule.range_max as i32);
-                            *value = clamped_val.to_string();
-                        }
-                    }
-                    ParamType::TypeFloat => {
-                        if let Ok(val) = value.parse::<f32>() {
-                            let clamped_val = val.clamp(rule.range_min, rule.range_max);
-                            *value = clamped_val.to_string();
-                        }
-                    }
-                    ParamType::TypeBool => {
-                        if value == "true" || value == "false" {
-                            *value = value.to_string();
-                        } else {
-                            return false;
-                        }
-                    }
-                    ParamType::TypeString => {}
-                },
-                ParamRange::RangeEnum => {
-                    if !rule.enum_vals.contains(value) {
-                        return false;
-                    }
-                }
+    // Same as register_callback, but the listener lives only as long as the returned Subscription.
+    pub fn register_subscription<F>(manager: &Arc<Mutex<ParameterManager>>, key: &str, callback: F) -> Subscription
+    where
+        F: Fn(String, String) + Send + Sync + 'static,
+    {
+        let listener_id = manager.lock().unwrap().register_callback(key, callback);
+        Subscription {
+            manager: Arc::downgrade(manager),
+            listener_id: Some(listener_id),
+        }
+    }
+
+    // Coerces the value into the rule's type, then applies the range. false means the value is rejected.
+    pub fn filter_value_with_rule(&self, key: &str, value: &mut ParamValue) -> bool {
+        match self.check_value_with_rule(key, value.clone()) {
+            Ok((_, filtered)) => {
+                *value = filtered;
+                true
             }
+            Err(_) => false,
         }
-        true
+    }
+
+    // Returns the value coerced into the rule's type and the value to store, which differ when clamped.
+    pub fn check_value_with_rule(&self, key: &str, value: ParamValue) -> Result<(ParamValue, ParamValue), RejectReason> {
+        let Some(rule) = self.rule_for(key) else {
+            return Ok((value.clone(), value));
+        };
+        let requested = value.coerce_to(rule.param_type).ok_or(RejectReason::TypeMismatch {
+            expected: rule.param_type,
+            actual: value.param_type(),
+        })?;
+        let mut value = requested.clone();
+        rule.clamp(&mut value)?;
+        rule.quantize(&mut value);
+        rule.check_constraints(&value)?;
+        Ok((requested, value))
     }
 
     pub fn execute_notify(&self, key: &str, value: &str, listeners: Vec<Listener>) {
@@ -201,52 +798,159 @@ impl ParameterManager {
         T: FromStr + Default,
         U: Into<T>,
     {
-        self.params
-            .get(key)
-            .and_then(|v| v.parse().ok())
+        self.value_or_default(key)
+            .and_then(|v| v.to_string().parse().ok())
             .unwrap_or_else(|| default_value.into())
     }
 
-    pub fn get_parameter_string(&self, key: &str, default_value: &str) -> String{
-        return self.get_parameter::<String, &str>(key, default_value);
+    pub fn get_parameter_value(&self, key: &str) -> Option<&ParamValue> {
+        self.params.get(key)
     }
 
-    pub fn get_parameter_int(&self, key: &str, default_value: i32) -> i32{
-        self.params
-            .get(key)
-            .and_then(|v| v.parse::<f64>().ok().map(|f| f as i32))
-            .unwrap_or(default_value)
+    // Every value has a textual form, so this getter cannot fail.
+    pub fn get_parameter_string(&self, key: &str, default_value: &str) -> String {
+        self.value_or_default(key)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| default_value.to_string())
     }
 
-    pub fn get_parameter_float(&self, key: &str, default_value: f32) -> f32{
-        return self.get_parameter::<f32, f32>(key, default_value);
+    // The typed getters fall back to the rule's default when the key is absent,
+    // and to default_value only when there is neither.
+    pub fn get_parameter_int(&self, key: &str, default_value: i32) -> Result<i32, ParamError> {
+        self.get_parameter_typed(key, i64::from(default_value), ParamType::TypeInt, |v| v.as_i64())
+            .and_then(|val| {
+                i32::try_from(val).map_err(|_| ParamError::OutOfRange {
+                    key: key.to_string(),
+                    value: ParamValue::Int(val),
+                })
+            })
     }
 
-    pub fn get_parameter_bool(&self, key: &str, default_value: bool) -> bool{
-        if self.get_parameter_string(key, &default_value.to_string()) == "true" {
-            true
-        } else {
-            false
+    pub fn get_parameter_i64(&self, key: &str, default_value: i64) -> Result<i64, ParamError> {
+        self.get_parameter_typed(key, default_value, ParamType::TypeInt, |v| v.as_i64())
+    }
+
+    pub fn get_parameter_u64(&self, key: &str, default_value: u64) -> Result<u64, ParamError> {
+        self.get_parameter_typed(key, default_value, ParamType::TypeUInt, |v| v.as_u64())
+    }
+
+    pub fn get_parameter_f64(&self, key: &str, default_value: f64) -> Result<f64, ParamError> {
+        self.get_parameter_typed(key, default_value, ParamType::TypeFloat, |v| v.as_f64())
+    }
+
+    pub fn get_parameter_float(&self, key: &str, default_value: f32) -> Result<f32, ParamError> {
+        self.get_parameter_typed(key, f64::from(default_value), ParamType::TypeFloat, |v| v.as_f64())
+            .map(|val| val as f32)
+    }
+
+    pub fn get_parameter_bool(&self, key: &str, default_value: bool) -> Result<bool, ParamError> {
+        self.get_parameter_typed(key, default_value, ParamType::TypeBool, |v| v.as_bool())
+    }
+
+    fn get_parameter_typed<T, F>(&self, key: &str, default_value: T, expected: ParamType, convert: F) -> Result<T, ParamError>
+    where
+        F: Fn(&ParamValue) -> Option<T>,
+    {
+        match self.value_or_default(key) {
+            None => Ok(default_value),
+            Some(value) => convert(&value).ok_or_else(|| ParamError::TypeMismatch {
+                key: key.to_string(),
+                expected,
+                actual: value.param_type(),
+            }),
         }
     }
 
-    pub fn set_parameter_rule(&mut self, key: &str, rule: ParamRule) {
-        self.param_rules.insert(key.to_string(), rule);
+    // A key ending with '*' installs a wild card rule for every key with that prefix.
+    // Values already stored under the rule are checked again: they are converted or clamped
+    // when possible, otherwise reset to the rule's default. Listeners see every change.
+    // A rule covering keys owned through an access policy is left out, see set_parameter_rule_as.
+    pub fn set_parameter_rule(&mut self, key: &str, rule: ParamRule) -> Vec<Revalidation> {
+        self.set_parameter_rule_with_token(key, rule, None).unwrap_or_default()
+    }
+
+    // Presents token to the owners of the keys the rule covers; Denied unless it is theirs.
+    pub fn set_parameter_rule_as(&mut self, token: &str, key: &str, rule: ParamRule) -> Result<Vec<Revalidation>, ParamError> {
+        self.set_parameter_rule_with_token(key, rule, Some(token))
+    }
+
+    fn set_parameter_rule_with_token(&mut self, key: &str, rule: ParamRule, token: Option<&str>) -> Result<Vec<Revalidation>, ParamError> {
+        if !self.may_set_rule(key, token) {
+            return Err(ParamError::Denied { key: key.to_string() });
+        }
+        if let Some(persistence) = &self.persistence {
+            persistence.set_rule(key, rule.clone());
+        }
+        let affected: Vec<String> = match key.strip_suffix('*') {
+            Some(prefix) => {
+                self.wild_card_rules.insert(prefix.to_string(), rule);
+                let mut keys: Vec<String> = self.params.keys().filter(|k| k.starts_with(prefix)).cloned().collect();
+                keys.sort();
+                keys
+            }
+            None => {
+                self.param_rules.insert(key.to_string(), rule);
+                if self.params.contains_key(key) { vec![key.to_string()] } else { Vec::new() }
+            }
+        };
+        Ok(affected.iter().filter_map(|key| self.revalidate(key)).collect())
+    }
+
+    fn revalidate(&mut self, key: &str) -> Option<Revalidation> {
+        let current = self.params.get(key)?.clone();
+        let revalidation = match self.check_value_with_rule(key, current.clone()) {
+            Ok((_, value)) if value == current => return None,
+            Ok((_, value)) => Revalidation::Adjusted {
+                key: key.to_string(),
+                from: current.clone(),
+                to: value,
+            },
+            Err(reason) => match self.rule_for(key).and_then(|rule| rule.default_value.clone()) {
+                Some(default_value) => Revalidation::Reset {
+                    key: key.to_string(),
+                    from: current.clone(),
+                    to: default_value,
+                    reason,
+                },
+                None => {
+                    return Some(Revalidation::Invalid {
+                        key: key.to_string(),
+                        value: current,
+                        reason,
+                    });
+                }
+            },
+        };
+        // owned and write-once values are reported, never rewritten
+        if self.check_access(key, None).is_some() {
+            let reason = match revalidation {
+                Revalidation::Reset { reason, .. } => reason,
+                _ => RejectReason::Protected,
+            };
+            return Some(Revalidation::Invalid {
+                key: key.to_string(),
+                value: current,
+                reason,
+            });
+        }
+        match &revalidation {
+            Revalidation::Adjusted { to, .. } | Revalidation::Reset { to, .. } => self.store_value(key, to.clone()),
+            Revalidation::Invalid { .. } => {}
+        }
+        Some(revalidation)
     }
 
     pub fn get_parameter_rule(&self, key: &str) -> ParamRule {
-        self.param_rules.get(key).cloned().unwrap_or_else(|| ParamRule {
-            param_type: ParamType::TypeString,
-            range: ParamRange::RangeAny,
-            range_min: 0.0,
-            range_max: 0.0,
-            enum_vals: HashSet::new(),
-        })
+        self.rule_for(key).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn rule_for(&self, key: &str) -> Option<&ParamRule> {
+        lookup_pattern(&self.param_rules, &self.wild_card_rules, key)
     }
 
     pub fn store_to_stream<W: Write>(&self, writer: &mut W) -> bool {
         let mut result = false;
-        for (key, value) in &self.params {
+        for (key, value) in self.params.iter().filter(|(key, _)| !self.is_hidden(key)) {
             let buf = format!("\"{}\":\"{}\"\n", key, value);
             if writer.write_all(buf.as_bytes()).is_ok() {
                 result = true;
@@ -274,4 +978,4 @@ impl ParameterManager {
 
         result
     }
-}
+}
\ No newline at end of file
@@ -0,0 +1,297 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Listener patterns:
+//   audio.volume      exact key
+//   audio.*           every key starting with "audio." (a trailing '*' is a plain prefix match)
+//   audio.*.volume    '*' matches one segment, or part of one as in audio.eq*.gain
+//   audio.**          '**' matches any number of segments, including none
+// Regular expressions are registered separately as pattern text, flags inline as in "(?i)audio",
+// and matched like Regex::is_match.
+//
+// Exact keys and prefixes are found with one hash lookup per prefix of the key, glob patterns
+// live in a trie of segments and regular expressions are compiled into RegexSets of up to
+// REGEX_SET_SIZE patterns, so the cost of a dispatch does not grow with the number of listeners
+// that do not match, and registering one only recompiles a single set.
+
+use std::collections::HashMap;
+
+use regex::RegexSet;
+
+const REGEX_SET_SIZE: usize = 64;
+
+use crate::Listener;
+
+pub(crate) trait IndexedListener: Clone {
+    fn listener_id(&self) -> usize;
+}
+
+impl IndexedListener for Listener {
+    fn listener_id(&self) -> usize {
+        self.listener_id
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ListenerIndex<L> {
+    exact: HashMap<String, Vec<L>>,
+    prefixes: HashMap<String, Vec<L>>,
+    globs: GlobNode<L>,
+    regex_sets: Vec<RegexGroup<L>>,
+    patterns: HashMap<usize, Pattern>,
+}
+
+// never empty; listeners[i] is called for set pattern i
+#[derive(Clone)]
+struct RegexGroup<L> {
+    set: RegexSet,
+    listeners: Vec<(String, L)>,
+}
+
+#[derive(Clone)]
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+    Glob(String),
+    Regex,
+}
+
+#[derive(Clone)]
+struct GlobNode<L> {
+    literal: HashMap<String, GlobNode<L>>,
+    // segments holding a '*', keyed by the segment pattern
+    wild_card: HashMap<String, GlobNode<L>>,
+    // "**"
+    any_depth: Option<Box<GlobNode<L>>>,
+    listeners: Vec<L>,
+}
+
+impl<L> Default for GlobNode<L> {
+    fn default() -> Self {
+        GlobNode {
+            literal: HashMap::new(),
+            wild_card: HashMap::new(),
+            any_depth: None,
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl<L> Default for ListenerIndex<L> {
+    fn default() -> Self {
+        ListenerIndex {
+            exact: HashMap::new(),
+            prefixes: HashMap::new(),
+            globs: GlobNode::default(),
+            regex_sets: Vec::new(),
+            patterns: HashMap::new(),
+        }
+    }
+}
+
+// '*' within a single segment, e.g. "eq*" or "*_gain"
+fn segment_matches(pattern: &str, segment: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == segment,
+        Some((head, tail)) => {
+            let Some(rest) = segment.strip_prefix(head) else {
+                return false;
+            };
+            (0..=rest.len())
+                .filter(|&start| rest.is_char_boundary(start))
+                .any(|start| segment_matches(tail, &rest[start..]))
+        }
+    }
+}
+
+impl<L: IndexedListener> GlobNode<L> {
+    fn is_empty(&self) -> bool {
+        self.listeners.is_empty() && self.literal.is_empty() && self.wild_card.is_empty() && self.any_depth.is_none()
+    }
+
+    fn child_mut(&mut self, segment: &str) -> &mut GlobNode<L> {
+        if segment == "**" {
+            self.any_depth.get_or_insert_with(Default::default)
+        } else if segment.contains('*') {
+            self.wild_card.entry(segment.to_string()).or_default()
+        } else {
+            self.literal.entry(segment.to_string()).or_default()
+        }
+    }
+
+    fn insert(&mut self, segments: &[&str], listener: L) {
+        match segments.split_first() {
+            None => self.listeners.push(listener),
+            Some((segment, rest)) => self.child_mut(segment).insert(rest, listener),
+        }
+    }
+
+    // Prunes the nodes left empty on the way back.
+    fn remove(&mut self, segments: &[&str], listener_id: usize) {
+        let Some((segment, rest)) = segments.split_first() else {
+            self.listeners.retain(|listener| listener.listener_id() != listener_id);
+            return;
+        };
+        if *segment == "**" {
+            if let Some(child) = self.any_depth.as_mut() {
+                child.remove(rest, listener_id);
+                if child.is_empty() {
+                    self.any_depth = None;
+                }
+            }
+            return;
+        }
+        let children = if segment.contains('*') { &mut self.wild_card } else { &mut self.literal };
+        if let Some(child) = children.get_mut(*segment) {
+            child.remove(rest, listener_id);
+            if child.is_empty() {
+                children.remove(*segment);
+            }
+        }
+    }
+
+    fn collect(&self, segments: &[&str], result: &mut Vec<L>) {
+        if let Some(child) = &self.any_depth {
+            for start in 0..=segments.len() {
+                child.collect(&segments[start..], result);
+            }
+        }
+        let Some((segment, rest)) = segments.split_first() else {
+            result.extend(self.listeners.iter().cloned());
+            return;
+        };
+        if let Some(child) = self.literal.get(*segment) {
+            child.collect(rest, result);
+        }
+        for (pattern, child) in &self.wild_card {
+            if segment_matches(pattern, segment) {
+                child.collect(rest, result);
+            }
+        }
+    }
+}
+
+impl<L: IndexedListener> ListenerIndex<L> {
+    pub(crate) fn insert(&mut self, pattern: &str, listener: L) {
+        let listener_id = listener.listener_id();
+        let pattern = match pattern.find('*') {
+            None => {
+                self.exact.entry(pattern.to_string()).or_default().push(listener);
+                Pattern::Exact(pattern.to_string())
+            }
+            Some(index) if index == pattern.len() - 1 => {
+                let prefix = &pattern[..index];
+                self.prefixes.entry(prefix.to_string()).or_default().push(listener);
+                Pattern::Prefix(prefix.to_string())
+            }
+            Some(_) => {
+                self.globs.insert(&pattern.split('.').collect::<Vec<_>>(), listener);
+                Pattern::Glob(pattern.to_string())
+            }
+        };
+        self.patterns.insert(listener_id, pattern);
+    }
+
+    // Joins the last set while it has room and still compiles, otherwise starts a new one.
+    pub(crate) fn insert_regex(&mut self, pattern: &str, listener: L) -> Result<(), regex::Error> {
+        let joined = match self.regex_sets.last_mut() {
+            Some(group) if group.listeners.len() < REGEX_SET_SIZE => {
+                let patterns = group.listeners.iter().map(|(pattern, _)| pattern.as_str()).chain([pattern]);
+                match RegexSet::new(patterns) {
+                    Ok(set) => {
+                        group.set = set;
+                        group.listeners.push((pattern.to_string(), listener.clone()));
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        };
+        if !joined {
+            self.regex_sets.push(RegexGroup {
+                set: RegexSet::new([pattern])?,
+                listeners: vec![(pattern.to_string(), listener.clone())],
+            });
+        }
+        self.patterns.insert(listener.listener_id(), Pattern::Regex);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, listener_id: usize) -> bool {
+        let Some(pattern) = self.patterns.remove(&listener_id) else {
+            return false;
+        };
+        let remove_from = |map: &mut HashMap<String, Vec<L>>, key: &str| {
+            if let Some(entries) = map.get_mut(key) {
+                entries.retain(|listener| listener.listener_id() != listener_id);
+                if entries.is_empty() {
+                    map.remove(key);
+                }
+            }
+        };
+        match pattern {
+            Pattern::Exact(key) => remove_from(&mut self.exact, &key),
+            Pattern::Prefix(prefix) => remove_from(&mut self.prefixes, &prefix),
+            Pattern::Glob(glob) => self.globs.remove(&glob.split('.').collect::<Vec<_>>(), listener_id),
+            Pattern::Regex => self.remove_regex(listener_id),
+        }
+        true
+    }
+
+    fn remove_regex(&mut self, listener_id: usize) {
+        let Some(index) = self
+            .regex_sets
+            .iter()
+            .position(|group| group.listeners.iter().any(|(_, listener)| listener.listener_id() == listener_id))
+        else {
+            return;
+        };
+        let group = &mut self.regex_sets[index];
+        group.listeners.retain(|(_, listener)| listener.listener_id() != listener_id);
+        if group.listeners.is_empty() {
+            self.regex_sets.remove(index);
+        } else {
+            group.set = RegexSet::new(group.listeners.iter().map(|(pattern, _)| pattern.as_str()))
+                .expect("a subset of a compiled RegexSet compiles");
+        }
+    }
+
+    // Prefix listeners first (shortest prefix first), then globs and regular expressions,
+    // exact listeners last. A listener matching through several paths is called once.
+    pub(crate) fn matching(&self, key: &str) -> Vec<L> {
+        let mut result = Vec::new();
+        for end in (0..=key.len()).filter(|&end| key.is_char_boundary(end)) {
+            if let Some(listeners) = self.prefixes.get(&key[..end]) {
+                result.extend(listeners.iter().cloned());
+            }
+        }
+
+        let mut globbed = Vec::new();
+        self.globs.collect(&key.split('.').collect::<Vec<_>>(), &mut globbed);
+        globbed.sort_by_key(|listener| listener.listener_id());
+        globbed.dedup_by_key(|listener| listener.listener_id());
+        result.extend(globbed);
+
+        for group in &self.regex_sets {
+            result.extend(group.set.matches(key).iter().map(|index| group.listeners[index].1.clone()));
+        }
+        if let Some(listeners) = self.exact.get(key) {
+            result.extend(listeners.iter().cloned());
+        }
+        result
+    }
+}
@@ -0,0 +1,323 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use datamanager::{ParamError, ParamValue, ParameterManager, Schema, SetOutcome};
+
+const USAGE: &str = "\
+Usage: datamanager <command> [arguments]
+
+  get <file> <key>
+  set <file> <key> <value>
+  list <file> [prefix]
+  diff <fileA> <fileB>
+  validate <file> --schema <schema.json>
+  convert <input> <output> [--from json|typed-text] [--to json|typed-text]
+  watch <file> [--interval <ms>]
+
+Files ending with .json use the JSON layout, anything else the typed text format.
+--schema <schema.json> applies the schema's rules before any file is read, so the values are
+checked against them and set checks the new value too.";
+
+// Exit codes: 0 success, 1 rejected value, violations or differences, 2 usage or I/O error.
+type CliResult = Result<i32, String>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    TypedText,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Result<Format, String> {
+        match name {
+            "json" => Ok(Format::Json),
+            "typed-text" => Ok(Format::TypedText),
+            _ => Err(format!("unknown format {}, expected json or typed-text", name)),
+        }
+    }
+
+    fn of_path(path: &str) -> Format {
+        if Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) { Format::Json } else { Format::TypedText }
+    }
+}
+
+// Positional arguments and --name value options, in any order.
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = iter.next().ok_or_else(|| format!("--{} needs a value", name))?;
+                    options.push((name.to_string(), value.clone()));
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Args { positional, options })
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter().find(|(option, _)| option == name).map(|(_, value)| value.as_str())
+    }
+
+    // exactly N positional arguments
+    fn positional<const N: usize>(&self) -> Result<&[String; N], String> {
+        self.positional.as_slice().try_into().map_err(|_| usage_error())
+    }
+}
+
+fn usage_error() -> String {
+    format!("wrong number of arguments\n\n{}", USAGE)
+}
+
+fn errors_text(errors: &[ParamError]) -> String {
+    errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+fn new_manager(args: &Args) -> Result<ParameterManager, String> {
+    let mut manager = ParameterManager::new();
+    if let Some(schema) = args.option("schema") {
+        // rules only, applied defaults would be written into the file by set
+        let schema = Schema::load(schema).map_err(|err| err.to_string())?;
+        for (pattern, rule) in schema.rules() {
+            manager.set_parameter_rule(&pattern, rule.clone());
+        }
+    }
+    Ok(manager)
+}
+
+fn load(args: &Args, path: &str) -> Result<ParameterManager, String> {
+    // restore_from_file takes a missing file for an empty one
+    fs::metadata(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut manager = new_manager(args)?;
+    manager.restore_from_file(path, true).map_err(|errors| errors_text(&errors))?;
+    Ok(manager)
+}
+
+fn load_as(args: &Args, path: &str, format: Format) -> Result<ParameterManager, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut reader = BufReader::new(file);
+    let mut manager = new_manager(args)?;
+    match format {
+        Format::Json => manager.restore_from_json(&mut reader, true).map_err(|errors| errors_text(&errors))?,
+        Format::TypedText => manager.restore_from_typed_stream(&mut reader, true).map_err(|errors| errors_text(&errors))?,
+    };
+    Ok(manager)
+}
+
+fn get(args: &Args) -> CliResult {
+    let [path, key] = args.positional()?;
+    let manager = load(args, path)?;
+    match manager.get_parameter_value(key).cloned().or_else(|| manager.get_default(key)) {
+        Some(value) => {
+            println!("{}", value);
+            Ok(0)
+        }
+        None => Err(format!("{} is not set", key)),
+    }
+}
+
+// Without a rule a new key gets the type its text reads as, so a later load with a rule of that
+// type accepts it.
+fn infer_value(text: &str) -> ParamValue {
+    if let Ok(val) = text.parse::<i64>() {
+        ParamValue::Int(val)
+    } else if let Ok(val) = text.parse::<u64>() {
+        ParamValue::UInt(val)
+    } else if let Some(val) = text.parse::<f64>().ok().filter(|val| val.is_finite()) {
+        ParamValue::Float(val)
+    } else if let Ok(val) = text.parse::<bool>() {
+        ParamValue::Bool(val)
+    } else {
+        ParamValue::from(text)
+    }
+}
+
+fn has_json_rules(path: &str) -> bool {
+    Format::of_path(path) == Format::Json
+        && fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<serde_json::Value>(&contents).ok())
+            .is_some_and(|document| document.get("rules").is_some())
+}
+
+fn set(args: &Args) -> CliResult {
+    let [path, key, text] = args.positional()?;
+    let mut manager = load(args, path)?;
+    let mut value = ParamValue::from(text.as_str());
+    if manager.describe_parameter(key).rule.is_none() {
+        // without a rule the value keeps the type it already has in the file
+        value = match manager.get_parameter_value(key) {
+            Some(current) => value.coerce_to(current.param_type()).unwrap_or(value),
+            None => infer_value(text),
+        };
+    }
+    match manager.try_set_parameter(key, value) {
+        SetOutcome::Rejected { reason } => {
+            eprintln!("{}: {} is rejected, {}", key, text, reason);
+            return Ok(1);
+        }
+        SetOutcome::ReadOnly => {
+            eprintln!("{}", ParamError::ReadOnly { key: key.clone() });
+            return Ok(1);
+        }
+        SetOutcome::Denied => {
+            eprintln!("{}", ParamError::Denied { key: key.clone() });
+            return Ok(1);
+        }
+        SetOutcome::Clamped { from, to } => eprintln!("{}: {} is stored as {}", key, from, to),
+        _ => {}
+    }
+    // e.g. a file written by convert, whose rules type its values
+    if has_json_rules(path) {
+        manager.store_to_file_with_rules(path).map_err(|err| err.to_string())?;
+    } else {
+        manager.store_to_file(path).map_err(|err| err.to_string())?;
+    }
+    Ok(0)
+}
+
+fn list(args: &Args) -> CliResult {
+    let (path, prefix) = match args.positional.as_slice() {
+        [path] => (path, ""),
+        [path, prefix] => (path, prefix.as_str()),
+        _ => return Err(usage_error()),
+    };
+    let manager = load(args, path)?;
+    for (key, value) in manager.get_subtree(prefix) {
+        println!("{} = {}", key, value);
+    }
+    Ok(0)
+}
+
+fn diff(args: &Args) -> CliResult {
+    let [path_a, path_b] = args.positional()?;
+    let (a, b) = (load(args, path_a)?, load(args, path_b)?);
+    let keys: BTreeSet<String> = a.keys().into_iter().chain(b.keys()).collect();
+    let mut differences = 0;
+    for key in keys {
+        match (a.get_parameter_value(&key), b.get_parameter_value(&key)) {
+            (Some(old), None) => println!("- {} = {}", key, old),
+            (None, Some(new)) => println!("+ {} = {}", key, new),
+            (Some(old), Some(new)) if old != new => println!("~ {} = {} -> {}", key, old, new),
+            _ => continue,
+        }
+        differences += 1;
+    }
+    Ok(if differences == 0 { 0 } else { 1 })
+}
+
+fn validate(args: &Args) -> CliResult {
+    let [path] = args.positional()?;
+    let schema = args.option("schema").ok_or("validate needs --schema <schema.json>")?;
+    let schema = Schema::load(schema).map_err(|err| err.to_string())?;
+    let violations = schema.validate_file(path).map_err(|errors| errors_text(&errors))?;
+    for violation in &violations {
+        println!("{}: {}", violation.key, violation.message);
+    }
+    Ok(if violations.is_empty() { 0 } else { 1 })
+}
+
+fn convert(args: &Args) -> CliResult {
+    let [input, output] = args.positional()?;
+    let from = args.option("from").map(Format::from_name).transpose()?.unwrap_or_else(|| Format::of_path(input));
+    let to = args.option("to").map(Format::from_name).transpose()?.unwrap_or_else(|| Format::of_path(output));
+    let manager = load_as(args, input, from)?;
+
+    let file = File::create(output).map_err(|err| format!("{}: {}", output, err))?;
+    let mut writer = BufWriter::new(file);
+    match to {
+        // the rules are needed to read the values back with their types
+        Format::Json => manager.store_to_json(&mut writer, true).map_err(|err| err.to_string())?,
+        Format::TypedText => manager.store_to_typed_stream(&mut writer).map_err(|err| err.to_string())?,
+    }
+    writer.flush().map_err(|err| format!("{}: {}", output, err))?;
+    Ok(0)
+}
+
+fn watch(args: &Args) -> CliResult {
+    let [path] = args.positional()?;
+    let interval = match args.option("interval") {
+        Some(ms) => Duration::from_millis(ms.parse().map_err(|_| format!("invalid interval {}", ms))?),
+        None => Duration::from_millis(500),
+    };
+    let manager = Arc::new(Mutex::new(load(args, path)?));
+    manager.lock().unwrap().register_callback("*", |key, value| {
+        println!("{} = {}", key, value);
+        let _ = io::stdout().flush();
+    });
+    let _watcher = ParameterManager::watch_file(&manager, path, interval, |result| match result {
+        Ok(report) => {
+            for err in report.rejected {
+                eprintln!("{}", err);
+            }
+        }
+        Err(errors) => eprintln!("{}", errors_text(&errors)),
+    });
+    loop {
+        thread::park();
+    }
+}
+
+fn run(args: &[String]) -> CliResult {
+    let Some((command, rest)) = args.split_first() else {
+        return Err(USAGE.to_string());
+    };
+    let args = Args::parse(rest)?;
+    match command.as_str() {
+        "get" => get(&args),
+        "set" => set(&args),
+        "list" => list(&args),
+        "diff" => diff(&args),
+        "validate" => validate(&args),
+        "convert" => convert(&args),
+        "watch" => watch(&args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        _ => Err(format!("unknown command {}\n\n{}", command, USAGE)),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => process::exit(code),
+        Err(message) => {
+            eprintln!("datamanager: {}", message);
+            process::exit(2);
+        }
+    }
+}
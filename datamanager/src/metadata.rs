@@ -0,0 +1,119 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Presentation metadata for settings screens. It never affects how values are checked or stored.
+// In schema and JSON rule entries the fields sit next to the rule's own:
+//   "audio.volume": { "type": "Int", "min": 0, "max": 100, "display_name": "Volume",
+//                     "unit": "%", "tags": ["audio"], "visibility": "advanced",
+//                     "deprecated": "use audio.master.volume" }
+
+use std::collections::BTreeSet;
+
+use crate::{lookup_pattern, AccessPolicy, ParamRule, ParamValue, ParameterManager};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Visibility {
+    #[default]
+    User,
+    Advanced,
+    Developer,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParamMetadata {
+    pub display_name: Option<String>,
+    // when empty, the rule's description is shown
+    pub description: String,
+    pub unit: Option<String>,
+    pub tags: Vec<String>,
+    pub visibility: Visibility,
+    // the notice to show, e.g. which key replaces this one
+    pub deprecated: Option<String>,
+}
+
+// Everything a generic settings UI needs to render one key.
+#[derive(Clone)]
+pub struct ParamInfo {
+    pub key: String,
+    pub rule: Option<ParamRule>,
+    pub metadata: ParamMetadata,
+    pub access: AccessPolicy,
+    pub value: Option<ParamValue>,
+    pub default_value: Option<ParamValue>,
+}
+
+impl Visibility {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Visibility::User => "user",
+            Visibility::Advanced => "advanced",
+            Visibility::Developer => "developer",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Visibility> {
+        match name {
+            "user" => Some(Visibility::User),
+            "advanced" => Some(Visibility::Advanced),
+            "developer" => Some(Visibility::Developer),
+            _ => None,
+        }
+    }
+}
+
+impl ParameterManager {
+    // A pattern ending with '*' applies to every key with that prefix; an exact key wins,
+    // then the longest prefix.
+    pub fn set_parameter_metadata(&mut self, pattern: &str, metadata: ParamMetadata) {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self.wild_card_metadata.insert(prefix.to_string(), metadata),
+            None => self.metadata.insert(pattern.to_string(), metadata),
+        };
+    }
+
+    pub fn get_parameter_metadata(&self, key: &str) -> ParamMetadata {
+        let mut metadata = lookup_pattern(&self.metadata, &self.wild_card_metadata, key).cloned().unwrap_or_default();
+        if metadata.description.is_empty()
+            && let Some(rule) = self.rule_for(key)
+        {
+            metadata.description = rule.description.clone();
+        }
+        metadata
+    }
+
+    pub fn describe_parameter(&self, key: &str) -> ParamInfo {
+        ParamInfo {
+            key: key.to_string(),
+            rule: self.rule_for(key).cloned(),
+            metadata: self.get_parameter_metadata(key),
+            access: self.get_access_policy(key),
+            value: self.params.get(key).cloned(),
+            default_value: self.get_default(key),
+        }
+    }
+
+    // Every key that has a value, an exact rule or exact metadata, sorted. Hidden keys are left out.
+    pub fn describe_parameters(&self) -> Vec<ParamInfo> {
+        let keys: BTreeSet<&String> = self
+            .params
+            .keys()
+            .chain(self.param_rules.keys())
+            .chain(self.metadata.keys())
+            .filter(|key| !self.is_hidden(key))
+            .collect();
+        keys.into_iter().map(|key| self.describe_parameter(key)).collect()
+    }
+}
@@ -0,0 +1,358 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tempfile::NamedTempFile;
+
+use crate::{ParamError, ParamRule, ParamValue, ParameterManager};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PersistPolicy {
+    All,
+    // Android style, e.g. vec!["persist.".to_string()]
+    Prefixes(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+pub struct PersistOptions {
+    // Changes within this period are collapsed into one write. Zero writes on every change.
+    pub debounce: Duration,
+    pub policy: PersistPolicy,
+}
+
+pub(crate) struct FilePersistence {
+    path: PathBuf,
+    options: PersistOptions,
+    shared: Arc<PersistShared>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+struct PersistShared {
+    state: Mutex<PersistState>,
+    wakeup: Condvar,
+    // The persisted values as last written, with the rules needed to write them. Kept here so
+    // a change costs a copy of the changed key only. Also serializes the writes.
+    mirror: Mutex<ParameterManager>,
+}
+
+#[derive(Default)]
+struct PersistState {
+    // changed since the last write, None for a removed key
+    changes: HashMap<String, Option<ParamValue>>,
+    // the contents of the last write, which a file watcher must not take for an edit
+    written: Option<Vec<u8>>,
+    stopping: bool,
+    last_error: Option<ParamError>,
+}
+
+impl PersistPolicy {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            PersistPolicy::All => true,
+            PersistPolicy::Prefixes(prefixes) => prefixes.iter().any(|prefix| key.starts_with(prefix.as_str())),
+        }
+    }
+}
+
+impl Default for PersistOptions {
+    fn default() -> Self {
+        PersistOptions {
+            debounce: Duration::from_millis(100),
+            policy: PersistPolicy::All,
+        }
+    }
+}
+
+pub(crate) fn is_json_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+pub(crate) fn io_error(path: &Path, err: std::io::Error) -> ParamError {
+    ParamError::Io(format!("{}: {}", path.display(), err))
+}
+
+// Writes to a temp file in the same directory, fsyncs it and renames it over the target,
+// so a crash leaves either the old or the new file but never a truncated one.
+pub(crate) fn write_atomically<F>(path: &Path, write: F) -> Result<(), ParamError>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), ParamError>,
+{
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp = NamedTempFile::new_in(dir).map_err(|err| io_error(path, err))?;
+    {
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        write(&mut writer)?;
+        writer.flush().map_err(|err| io_error(path, err))?;
+    }
+    temp.as_file().sync_all().map_err(|err| io_error(path, err))?;
+    temp.persist(path).map_err(|err| io_error(path, err.error))?;
+    // make the rename itself durable
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+fn write_contents(path: &Path, contents: &[u8]) -> Result<(), ParamError> {
+    write_atomically(path, |writer| writer.write_all(contents).map_err(|err| io_error(path, err)))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+impl FilePersistence {
+    fn new(path: PathBuf, options: PersistOptions, mirror: ParameterManager) -> Self {
+        let shared = Arc::new(PersistShared {
+            state: Mutex::new(PersistState::default()),
+            wakeup: Condvar::new(),
+            mirror: Mutex::new(mirror),
+        });
+        let worker = if options.debounce.is_zero() {
+            None
+        } else {
+            let shared = shared.clone();
+            let path = path.clone();
+            let debounce = options.debounce;
+            Some(thread::spawn(move || Self::run(shared, path, debounce)))
+        };
+        FilePersistence {
+            path,
+            options,
+            shared,
+            worker,
+        }
+    }
+
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        self.options.policy.matches(key)
+    }
+
+    pub(crate) fn schedule(&self, changes: Vec<(String, Option<ParamValue>)>) {
+        self.shared.state.lock().unwrap().changes.extend(changes);
+        if self.worker.is_some() {
+            self.shared.wakeup.notify_all();
+        } else {
+            Self::write_pending(&self.shared, &self.path);
+        }
+    }
+
+    // Only affects how values are written, e.g. Enum() in typed text.
+    pub(crate) fn set_rule(&self, pattern: &str, rule: ParamRule) {
+        let mut mirror = self.shared.mirror.lock().unwrap();
+        match pattern.strip_suffix('*') {
+            Some(prefix) => mirror.wild_card_rules.insert(prefix.to_string(), rule),
+            None => mirror.param_rules.insert(pattern.to_string(), rule),
+        };
+    }
+
+    // Keys changed in memory but not in the file yet. Waits for a write in progress, whose
+    // changes are no longer pending.
+    pub(crate) fn pending_keys(&self) -> HashSet<String> {
+        let _mirror = self.shared.mirror.lock().unwrap();
+        self.shared.state.lock().unwrap().changes.keys().cloned().collect()
+    }
+
+    pub(crate) fn wrote(&self, contents: &[u8]) -> bool {
+        self.shared.state.lock().unwrap().written.as_deref() == Some(contents)
+    }
+
+    // Writes whatever is pending now and returns the first write error since the last flush.
+    pub(crate) fn flush(&self) -> Result<(), ParamError> {
+        Self::write_pending(&self.shared, &self.path);
+        match self.shared.state.lock().unwrap().last_error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn write_pending(shared: &PersistShared, path: &Path) {
+        // taken before the changes, so concurrent writers apply them in order
+        let mut mirror = shared.mirror.lock().unwrap();
+        let changes = std::mem::take(&mut shared.state.lock().unwrap().changes);
+        if changes.is_empty() {
+            return;
+        }
+        for (key, value) in changes {
+            match value {
+                Some(value) => mirror.params.insert(key, value),
+                None => mirror.params.remove(&key),
+            };
+        }
+        let result = mirror.file_contents(path, false).and_then(|contents| {
+            // recorded before the file changes, so a watcher polling meanwhile recognizes it
+            let previous = shared.state.lock().unwrap().written.replace(contents.clone());
+            write_contents(path, &contents).inspect_err(|_| shared.state.lock().unwrap().written = previous)
+        });
+        if let Err(err) = result {
+            shared.state.lock().unwrap().last_error = Some(err);
+        }
+    }
+
+    fn run(shared: Arc<PersistShared>, path: PathBuf, debounce: Duration) {
+        loop {
+            let mut state = shared.state.lock().unwrap();
+            while state.changes.is_empty() && !state.stopping {
+                state = shared.wakeup.wait(state).unwrap();
+            }
+            if !state.stopping {
+                // let the burst settle, only stopping cuts it short
+                state = shared.wakeup.wait_timeout_while(state, debounce, |s| !s.stopping).unwrap().0;
+            }
+            let stopping = state.stopping;
+            drop(state);
+
+            Self::write_pending(&shared, &path);
+            if stopping {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for FilePersistence {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopping = true;
+        self.shared.wakeup.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        Self::write_pending(&self.shared, &self.path);
+    }
+}
+
+impl ParameterManager {
+    // ".json" files use the JSON layout, anything else the typed text format.
+    pub fn store_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ParamError> {
+        let path = path.as_ref();
+        write_contents(path, &self.file_contents(path, false)?)
+    }
+
+    // Like store_to_file, but a JSON file also gets the "rules" section. Typed text keeps no rules.
+    pub fn store_to_file_with_rules<P: AsRef<Path>>(&self, path: P) -> Result<(), ParamError> {
+        let path = path.as_ref();
+        write_contents(path, &self.file_contents(path, true)?)
+    }
+
+    // What store_to_file writes to path.
+    pub(crate) fn file_contents(&self, path: &Path, include_rules: bool) -> Result<Vec<u8>, ParamError> {
+        let mut contents = Vec::new();
+        if is_json_path(path) {
+            self.store_to_json(&mut contents, include_rules)?;
+        } else {
+            self.store_to_typed_stream(&mut contents).map_err(|err| io_error(path, err))?;
+        }
+        Ok(contents)
+    }
+
+    // Keys with a change not yet written to path, when path is the bound file.
+    pub(crate) fn unpersisted_keys(&self, path: &Path) -> HashSet<String> {
+        match &self.persistence {
+            Some(persistence) if same_file(&persistence.path, path) => persistence.pending_keys(),
+            _ => HashSet::new(),
+        }
+    }
+
+    // true when path is the bound file and holds exactly what auto-persist last wrote to it
+    pub(crate) fn is_own_write(&self, path: &Path, contents: &[u8]) -> bool {
+        self.persistence
+            .as_ref()
+            .is_some_and(|persistence| same_file(&persistence.path, path) && persistence.wrote(contents))
+    }
+
+    // A missing file is not an error, it simply restores nothing.
+    pub fn restore_from_file<P: AsRef<Path>>(&mut self, path: P, override_existing: bool) -> Result<usize, Vec<ParamError>> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(vec![io_error(path, err)]),
+        };
+        let mut reader = BufReader::new(file);
+        if is_json_path(path) {
+            self.restore_from_json(&mut reader, override_existing)
+        } else {
+            self.restore_from_typed_stream(&mut reader, override_existing)
+        }
+    }
+
+    pub fn bind_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, Vec<ParamError>> {
+        self.bind_file_with_options(path, PersistOptions::default())
+    }
+
+    // Loads the file, then writes changes of keys matching the policy back to it.
+    pub fn bind_file_with_options<P: AsRef<Path>>(&mut self, path: P, options: PersistOptions) -> Result<usize, Vec<ParamError>> {
+        self.unbind_file().map_err(|err| vec![err])?;
+        let path = path.as_ref().to_path_buf();
+        let count = self.restore_from_file(&path, true)?;
+        // carries no access policy, so hidden keys are kept in the bound file
+        let mirror = self.snapshot_where(|key, _| options.policy.matches(key));
+        self.persistence = Some(Arc::new(FilePersistence::new(path, options, mirror)));
+        Ok(count)
+    }
+
+    // Writes any pending change and stops auto-persisting.
+    pub fn unbind_file(&mut self) -> Result<(), ParamError> {
+        match self.persistence.take() {
+            Some(persistence) => persistence.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn bound_file(&self) -> Option<&Path> {
+        self.persistence.as_ref().map(|persistence| persistence.path.as_path())
+    }
+
+    // Also reports journal write errors.
+    pub fn flush(&self) -> Result<(), ParamError> {
+        if let Some(persistence) = &self.persistence {
+            persistence.flush()?;
+        }
+        match &self.journal {
+            Some(journal) => journal.take_error(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn schedule_persist(&self, key: &str) {
+        self.schedule_persist_keys([key]);
+    }
+
+    // one snapshot for a whole batch of changed keys
+    pub(crate) fn schedule_persist_keys<'k, I: IntoIterator<Item = &'k str>>(&self, keys: I) {
+        let Some(persistence) = &self.persistence else {
+            return;
+        };
+        let changes: Vec<(String, Option<ParamValue>)> = keys
+            .into_iter()
+            .filter(|key| persistence.matches(key))
+            .map(|key| (key.to_string(), self.params.get(key).cloned()))
+            .collect();
+        if !changes.is_empty() {
+            persistence.schedule(changes);
+        }
+    }
+}
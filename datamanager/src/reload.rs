@@ -0,0 +1,186 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::json::parse_json_document;
+use crate::persist::{io_error, is_json_path};
+use crate::typed_text::parse_typed_line;
+use crate::{ParamError, ParamValue, ParameterManager, SetOutcome};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReloadReport {
+    // keys whose value was applied, in file order
+    pub changed: Vec<String>,
+    pub rejected: Vec<ParamError>,
+}
+
+// Polls a parameter file and reloads it into the manager when its contents change. Stops on drop.
+pub struct FileWatcher {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+// Reads the values of a parameter file without applying them or checking any rule.
+pub(crate) fn read_file_entries(path: &Path) -> Result<Vec<(String, ParamValue)>, Vec<ParamError>> {
+    let file = File::open(path).map_err(|err| vec![io_error(path, err)])?;
+    let mut reader = BufReader::new(file);
+    if is_json_path(path) {
+        return parse_json_document(&mut reader).map(|document| document.values).map_err(|err| vec![err]);
+    }
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        match line {
+            Ok(line) => match parse_typed_line(index + 1, &line) {
+                Ok(Some(entry)) => entries.push((entry.key, entry.value)),
+                Ok(None) => {}
+                Err(err) => errors.push(err),
+            },
+            Err(err) => {
+                errors.push(io_error(path, err));
+                break;
+            }
+        }
+    }
+    if errors.is_empty() { Ok(entries) } else { Err(errors) }
+}
+
+fn read_contents(path: &Path) -> Option<Vec<u8>> {
+    std::fs::read(path).ok()
+}
+
+impl FileWatcher {
+    pub fn stop(&mut self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+
+    fn run<F>(manager: Weak<Mutex<ParameterManager>>, path: PathBuf, interval: Duration, stop: Arc<(Mutex<bool>, Condvar)>, mut last_contents: Option<Vec<u8>>, on_reload: F)
+    where
+        F: Fn(Result<ReloadReport, Vec<ParamError>>),
+    {
+        loop {
+            {
+                let stopped = stop.0.lock().unwrap();
+                let (stopped, _) = stop.1.wait_timeout_while(stopped, interval, |stopped| !*stopped).unwrap();
+                if *stopped {
+                    break;
+                }
+            }
+            let contents = read_contents(&path);
+            if contents.is_none() || contents == last_contents {
+                // a missing file is treated as a file being replaced, wait for it to come back
+                continue;
+            }
+            last_contents = contents;
+
+            let Some(manager) = manager.upgrade() else {
+                break;
+            };
+            let mut manager = manager.lock().unwrap();
+            if last_contents.as_deref().is_some_and(|contents| manager.is_own_write(&path, contents)) {
+                continue;
+            }
+            let result = manager.reload_from_file(&path);
+            drop(manager);
+            on_reload(result);
+        }
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl ParameterManager {
+    // Applies only the keys whose value differs from the current one, so listeners fire as for
+    // an in-process change. Keys missing from the file are left untouched, and so are keys whose
+    // newer value is still waiting to be written to the bound file.
+    pub fn reload_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<ReloadReport, Vec<ParamError>> {
+        let unpersisted = self.unpersisted_keys(path.as_ref());
+        let entries = read_file_entries(path.as_ref())?;
+        let mut report = ReloadReport::default();
+
+        self.history.begin_group();
+        for (key, value) in entries {
+            if unpersisted.contains(&key) {
+                continue;
+            }
+            let filtered = match self.check_change(&key, value.clone()) {
+                Ok((_, filtered)) => filtered,
+                Err(reason) => {
+                    report.rejected.push(ParamError::Rejected { key, value, reason });
+                    continue;
+                }
+            };
+            if self.params.get(&key) == Some(&filtered) {
+                continue;
+            }
+            // stored directly, the veto listeners already had their say
+            match self.check_access(&key, None) {
+                Some(SetOutcome::Denied) => report.rejected.push(ParamError::Denied { key }),
+                Some(_) => report.rejected.push(ParamError::ReadOnly { key }),
+                None => {
+                    self.store_value(&key, filtered);
+                    report.changed.push(key);
+                }
+            }
+        }
+        self.history.end_group();
+        Ok(report)
+    }
+
+    pub fn reload_bound_file(&mut self) -> Result<ReloadReport, Vec<ParamError>> {
+        let path = self.bound_file().map(Path::to_path_buf);
+        match path {
+            Some(path) => self.reload_from_file(path),
+            None => Err(vec![ParamError::Io("no file is bound".to_string())]),
+        }
+    }
+
+    // Polls path every interval; on_reload receives the outcome of every reload.
+    pub fn watch_file<P, F>(manager: &Arc<Mutex<ParameterManager>>, path: P, interval: Duration, on_reload: F) -> FileWatcher
+    where
+        P: AsRef<Path>,
+        F: Fn(Result<ReloadReport, Vec<ParamError>>) + Send + 'static,
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let worker = {
+            let manager = Arc::downgrade(manager);
+            let path = path.as_ref().to_path_buf();
+            let stop = stop.clone();
+            // taken on the caller's thread, so an edit right after watch_file() returns is not missed
+            let contents = read_contents(&path);
+            thread::spawn(move || FileWatcher::run(manager, path, interval, stop, contents, on_reload))
+        };
+        FileWatcher {
+            stop,
+            worker: Some(worker),
+        }
+    }
+}
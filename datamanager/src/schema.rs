@@ -0,0 +1,163 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Schema file layout, the same as the "rules" section of a JSON parameter document:
+//   {
+//     "rules": {
+//       "audio.volume": { "type": "Int", "min": 0, "max": 100, "default": 50,
+//                         "description": "Master volume", "unit": "%" },
+//       "audio.mode": { "type": "String", "enum": ["low", "high"], "default": "low" },
+//       "ro.audio.*": { "type": "String", "read_only": true }
+//     }
+//   }
+// A pattern ending with '*' applies to every key with that prefix. Metadata fields, see
+// metadata.rs, may sit next to the rule's.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::json::parse_json_document;
+use crate::persist::io_error;
+use crate::reload::read_file_entries;
+use crate::{lookup_pattern, ParamError, ParamMetadata, ParamRule, ParamValue, ParameterManager};
+
+#[derive(Clone, Default)]
+pub struct Schema {
+    param_rules: HashMap<String, ParamRule>,
+    wild_card_rules: HashMap<String, ParamRule>,
+    // by pattern, wild card patterns keep their trailing '*'
+    metadata: HashMap<String, ParamMetadata>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaViolation {
+    pub key: String,
+    pub message: String,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Schema, ParamError> {
+        let document = parse_json_document(reader)?;
+        let mut schema = Schema::new();
+        for (pattern, rule) in document.rules {
+            schema.set_rule(&pattern, rule);
+        }
+        schema.metadata.extend(document.metadata);
+        Ok(schema)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Schema, ParamError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| io_error(path, err))?;
+        Self::from_reader(&mut BufReader::new(file))
+    }
+
+    pub fn set_rule(&mut self, pattern: &str, rule: ParamRule) {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self.wild_card_rules.insert(prefix.to_string(), rule),
+            None => self.param_rules.insert(pattern.to_string(), rule),
+        };
+    }
+
+    pub fn rule_for(&self, key: &str) -> Option<&ParamRule> {
+        lookup_pattern(&self.param_rules, &self.wild_card_rules, key)
+    }
+
+    // (pattern, rule) pairs sorted by pattern; wild card patterns keep their trailing '*'
+    pub fn rules(&self) -> Vec<(String, &ParamRule)> {
+        let wild_card_rules = self.wild_card_rules.iter().map(|(prefix, rule)| (format!("{}*", prefix), rule));
+        let mut rules: Vec<(String, &ParamRule)> = self
+            .param_rules
+            .iter()
+            .map(|(key, rule)| (key.clone(), rule))
+            .chain(wild_card_rules)
+            .collect();
+        rules.sort_by(|a, b| a.0.cmp(&b.0));
+        rules
+    }
+
+    // Unlike set_parameter, out-of-range values are reported instead of being clamped.
+    pub fn validate_value(&self, key: &str, value: &ParamValue) -> Option<SchemaViolation> {
+        let violation = |message: String| {
+            Some(SchemaViolation {
+                key: key.to_string(),
+                message,
+            })
+        };
+        let Some(rule) = self.rule_for(key) else {
+            return violation("not declared in the schema".to_string());
+        };
+        let Some(value) = value.coerce_to(rule.param_type) else {
+            return violation(format!("{} is not {}", value, rule.param_type.name()));
+        };
+        let mut clamped = value.clone();
+        if let Err(reason) = rule.clamp(&mut clamped) {
+            return violation(format!("{} is {}", value, reason));
+        }
+        if clamped != value {
+            return violation(format!("{} is out of range {}", value, rule.range_text().unwrap_or_default()));
+        }
+        let mut quantized = value.clone();
+        rule.quantize(&mut quantized);
+        if quantized != value {
+            return violation(format!("{} is not a multiple of the step", value));
+        }
+        rule.check_constraints(&value).err().and_then(|reason| violation(format!("{} is {}", value, reason)))
+    }
+
+    pub fn validate(&self, entries: &[(String, ParamValue)]) -> Vec<SchemaViolation> {
+        entries
+            .iter()
+            .filter_map(|(key, value)| self.validate_value(key, value))
+            .collect()
+    }
+
+    // Checks a parameter file without loading it into any manager.
+    pub fn validate_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<SchemaViolation>, Vec<ParamError>> {
+        read_file_entries(path.as_ref()).map(|entries| self.validate(&entries))
+    }
+}
+
+impl ParameterManager {
+    // Installs every rule and metadata entry of the schema and sets the defaults of exact keys that have no value yet.
+    // Returns the number of defaults applied.
+    pub fn apply_schema(&mut self, schema: &Schema) -> usize {
+        for (pattern, rule) in schema.rules() {
+            self.set_parameter_rule(&pattern, rule.clone());
+        }
+        for (pattern, metadata) in &schema.metadata {
+            self.set_parameter_metadata(pattern, metadata.clone());
+        }
+        let mut count = 0;
+        for (key, rule) in &schema.param_rules {
+            if let (Some(default_value), false) = (&rule.default_value, self.params.contains_key(key)) {
+                self.set_parameter(key, default_value.clone());
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn load_schema<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, ParamError> {
+        Schema::load(path).map(|schema| self.apply_schema(&schema))
+    }
+}
@@ -0,0 +1,124 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::collections::BTreeMap;
+
+use crate::{ParamError, ParamValue, ParameterManager, SetOutcome};
+
+// Sets staged by ParameterManager::transaction. Setting a key twice keeps the last value.
+pub struct Transaction<'a> {
+    manager: &'a ParameterManager,
+    staged: BTreeMap<String, (ParamValue, Option<String>)>,
+}
+
+impl Transaction<'_> {
+    pub fn set<T: Into<ParamValue>>(&mut self, key: &str, value: T) {
+        self.staged.insert(key.to_string(), (value.into(), None));
+    }
+
+    // presents token to keys that have an owner, see try_set_parameter_as
+    pub fn set_as<T: Into<ParamValue>>(&mut self, token: &str, key: &str, value: T) {
+        self.staged.insert(key.to_string(), (value.into(), Some(token.to_string())));
+    }
+
+    // the staged value if any, otherwise the stored one
+    pub fn get(&self, key: &str) -> Option<&ParamValue> {
+        match self.staged.get(key) {
+            Some((value, _)) => Some(value),
+            None => self.manager.get_parameter_value(key),
+        }
+    }
+}
+
+impl ParameterManager {
+    // Every staged value is checked before anything is applied: a single rejected or protected
+    // key fails the whole transaction. Veto listeners see the manager with every staged value in
+    // place, so they can check keys against each other. On commit the key listeners get one
+    // notification per changed key, sorted by key, then the batch listeners get all the changes
+    // at once. Returns the changed keys.
+    pub fn transaction<F>(&mut self, build: F) -> Result<Vec<String>, Vec<ParamError>>
+    where
+        F: FnOnce(&mut Transaction),
+    {
+        let mut transaction = Transaction {
+            manager: self,
+            staged: BTreeMap::new(),
+        };
+        build(&mut transaction);
+        let staged = transaction.staged;
+
+        // a copy with the staged values, only when some veto listener is going to look at it
+        let mut view = staged.keys().any(|key| !self.veto_listeners.matching(key).is_empty()).then(|| {
+            let mut view = self.snapshot_where(|_, _| true);
+            view.access_policies = self.access_policies.clone();
+            view.wild_card_access_policies = self.wild_card_access_policies.clone();
+            view.metadata = self.metadata.clone();
+            view.wild_card_metadata = self.wild_card_metadata.clone();
+            for (key, (value, _)) in &staged {
+                if let Ok((_, checked)) = self.check_value_with_rule(key, value.clone()) {
+                    view.params.insert(key.clone(), checked);
+                }
+            }
+            view
+        });
+
+        let mut changes = Vec::new();
+        let mut errors = Vec::new();
+        for (key, (value, token)) in staged {
+            let result = self.check_change_in(view.as_ref().unwrap_or(self), &key, value.clone());
+            // later listeners see what a rewrite made of the value
+            if let (Some(view), Ok((_, checked))) = (view.as_mut(), &result) {
+                view.params.insert(key.clone(), checked.clone());
+            }
+            match result {
+                Ok((_, checked)) if self.params.get(&key) == Some(&checked) => {}
+                Ok((_, checked)) => match self.check_access(&key, token.as_deref()) {
+                    Some(SetOutcome::Denied) => errors.push(ParamError::Denied { key }),
+                    Some(_) => errors.push(ParamError::ReadOnly { key }),
+                    None => changes.push((key, checked)),
+                },
+                Err(reason) => errors.push(ParamError::Rejected { key, value, reason }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        self.history.begin_group();
+        for (key, value) in &changes {
+            let before = self.params.insert(key.clone(), value.clone());
+            self.journal_change(key, before.as_ref(), Some(value));
+            self.history.record(key, before, Some(value.clone()));
+        }
+        self.history.end_group();
+        self.schedule_persist_keys(changes.iter().map(|(key, _)| key.as_str()));
+        for (key, value) in &changes {
+            self.notify(key, value.to_string());
+        }
+        let batch_listeners = self.batch_listeners.clone();
+        if !changes.is_empty() {
+            match &self.dispatcher {
+                Some(dispatcher) => dispatcher.enqueue_batch(changes.clone(), batch_listeners),
+                None => {
+                    for listener in batch_listeners {
+                        (listener.callback.lock().unwrap())(changes.clone());
+                    }
+                }
+            }
+        }
+        Ok(changes.into_iter().map(|(key, _)| key).collect())
+    }
+}
@@ -0,0 +1,120 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Keys are dot separated paths such as "audio.eq.bass". Hidden keys are left out of every
+// listing and export below.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::reload::read_file_entries;
+use crate::{ParamError, ParamValue, ParameterManager, SetOutcome};
+
+impl ParameterManager {
+    // sorted
+    pub fn keys(&self) -> Vec<String> {
+        self.keys_with_prefix("")
+    }
+
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .params
+            .keys()
+            .filter(|key| key.starts_with(prefix) && !self.is_hidden(key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // Names of the direct children of a node, e.g. "audio" (or "audio.") gives ["eq", "volume"]
+    // for audio.eq.bass and audio.volume. An empty prefix gives the top level names.
+    pub fn children(&self, prefix: &str) -> Vec<String> {
+        let prefix = prefix.trim_end_matches('.');
+        let node = if prefix.is_empty() { String::new() } else { format!("{}.", prefix) };
+        // a set: "eq-preset" sorts between "eq" and "eq.bass"
+        let children: BTreeSet<String> = self
+            .keys_with_prefix(&node)
+            .iter()
+            .filter_map(|key| key[node.len()..].split('.').next().filter(|name| !name.is_empty()).map(str::to_string))
+            .collect();
+        children.into_iter().collect()
+    }
+
+    pub fn get_subtree(&self, prefix: &str) -> BTreeMap<String, ParamValue> {
+        self.keys_with_prefix(prefix)
+            .into_iter()
+            .map(|key| {
+                let value = self.params[&key].clone();
+                (key, value)
+            })
+            .collect()
+    }
+
+    // Hidden keys and keys the access policy protects from an anonymous write are kept, removals
+    // are not notified. Returns the removed keys, sorted.
+    pub fn remove_subtree(&mut self, prefix: &str) -> Vec<String> {
+        let mut keys = self.keys_with_prefix(prefix);
+        keys.retain(|key| self.check_access(key, None).is_none());
+        self.history.begin_group();
+        for key in &keys {
+            self.remove_value(key);
+        }
+        self.history.end_group();
+        keys
+    }
+
+    // Same formats as store_to_file, limited to the keys with the prefix.
+    pub fn store_subtree_to_file<P: AsRef<Path>>(&self, prefix: &str, path: P) -> Result<(), ParamError> {
+        self.snapshot_where(|key, _| key.starts_with(prefix) && !self.is_hidden(key)).store_to_file(path)
+    }
+
+    // Imports the keys of the file that start with prefix, replacing it by new_prefix when given,
+    // e.g. ("audio.", Some("backup.audio.")). All-or-nothing like restore_from_file.
+    pub fn restore_subtree_from_file<P: AsRef<Path>>(&mut self, path: P, prefix: &str, new_prefix: Option<&str>, override_existing: bool) -> Result<usize, Vec<ParamError>> {
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for (key, value) in read_file_entries(path.as_ref())? {
+            let Some(rest) = key.strip_prefix(prefix) else {
+                continue;
+            };
+            let key = format!("{}{}", new_prefix.unwrap_or(prefix), rest);
+            if !override_existing && self.params.contains_key(&key) {
+                continue;
+            }
+            match self.check_change(&key, value.clone()) {
+                Ok((_, checked)) if self.params.get(&key) == Some(&checked) => {}
+                Ok((_, checked)) => match self.check_access(&key, None) {
+                    Some(SetOutcome::Denied) => errors.push(ParamError::Denied { key }),
+                    Some(_) => errors.push(ParamError::ReadOnly { key }),
+                    None => entries.push((key, checked)),
+                },
+                Err(reason) => errors.push(ParamError::Rejected { key, value, reason }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let count = entries.len();
+        self.history.begin_group();
+        for (key, value) in entries {
+            self.store_value(&key, value);
+        }
+        self.history.end_group();
+        Ok(count)
+    }
+}
@@ -0,0 +1,227 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+// Typed text format, one entry per line:
+//   paramX = Float(3.14)
+//   name = String("quoted \"and\" escaped")
+//   mode = Enum(high)
+// Blank lines and lines starting with '#' are ignored.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+use crate::{ParamError, ParamRange, ParamRule, ParamValue, ParameterManager};
+
+pub(crate) struct TypedEntry {
+    pub line: usize,
+    pub key: String,
+    pub value: ParamValue,
+    pub is_enum: bool,
+}
+
+pub(crate) fn parse_typed_line(line_no: usize, line: &str) -> Result<Option<TypedEntry>, ParamError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let malformed = |message: &str| ParamError::Parse {
+        line: line_no,
+        message: message.to_string(),
+    };
+
+    let (key, typed) = line.split_once('=').ok_or_else(|| malformed("missing '='"))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(malformed("empty key"));
+    }
+    let typed = typed.trim();
+    let (type_name, rest) = typed.split_once('(').ok_or_else(|| malformed("missing '('"))?;
+    let raw = rest.strip_suffix(')').ok_or_else(|| malformed("missing ')'"))?.trim();
+
+    let text = if raw.starts_with('"') {
+        unescape(raw).ok_or_else(|| malformed("unterminated or invalid quoted string"))?
+    } else {
+        raw.to_string()
+    };
+
+    let type_name = type_name.trim();
+    let (value, is_enum) = match type_name {
+        "Int" => (text.parse::<i64>().map(ParamValue::Int).map_err(|_| malformed("invalid Int"))?, false),
+        "UInt" => (text.parse::<u64>().map(ParamValue::UInt).map_err(|_| malformed("invalid UInt"))?, false),
+        "Float" => (text.parse::<f64>().map(ParamValue::Float).map_err(|_| malformed("invalid Float"))?, false),
+        "Bool" => (text.parse::<bool>().map(ParamValue::Bool).map_err(|_| malformed("invalid Bool"))?, false),
+        "String" => (ParamValue::String(text), false),
+        "Enum" => (ParamValue::String(text), true),
+        _ => return Err(malformed(&format!("unknown type {}", type_name))),
+    };
+
+    Ok(Some(TypedEntry {
+        line: line_no,
+        key: key.to_string(),
+        value,
+        is_enum,
+    }))
+}
+
+pub(crate) fn format_typed_line(key: &str, value: &ParamValue, is_enum: bool) -> String {
+    match value {
+        ParamValue::String(val) if is_enum => format!("{} = Enum({})", key, val),
+        ParamValue::String(val) => format!("{} = String({})", key, escape(val)),
+        ParamValue::Int(val) => format!("{} = Int({})", key, val),
+        ParamValue::UInt(val) => format!("{} = UInt({})", key, val),
+        ParamValue::Float(val) => format!("{} = Float({:?})", key, val),
+        ParamValue::Bool(val) => format!("{} = Bool({})", key, val),
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn unescape(quoted: &str) -> Option<String> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '"' => result.push('"'),
+                '\\' => result.push('\\'),
+                'n' => result.push('\n'),
+                'r' => result.push('\r'),
+                't' => result.push('\t'),
+                _ => return None,
+            },
+            '"' => return None,
+            _ => result.push(c),
+        }
+    }
+    Some(result)
+}
+
+impl ParameterManager {
+    pub fn store_to_typed_stream<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let mut keys: Vec<&String> = self.params.keys().filter(|key| !self.is_hidden(key)).collect();
+        keys.sort();
+        for key in keys {
+            let is_enum = matches!(self.rule_for(key), Some(ParamRule { range: ParamRange::RangeEnum, .. }));
+            writeln!(writer, "{}", format_typed_line(key, &self.params[key], is_enum))?;
+        }
+        Ok(())
+    }
+
+    // All-or-nothing: every malformed line is reported with its line number and nothing is applied.
+    // Keys without a rule get one of the declared type, or an enum of the values given by the file;
+    // keys with a rule must match it.
+    pub fn restore_from_typed_stream<R: BufRead>(&mut self, reader: &mut R, override_existing: bool) -> Result<usize, Vec<ParamError>> {
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    errors.push(ParamError::Io(err.to_string()));
+                    break;
+                }
+            };
+            match parse_typed_line(index + 1, &line) {
+                Ok(Some(entry)) => match self.check_typed_entry(&entry) {
+                    Ok(value) => entries.push((entry, value)),
+                    Err(err) => errors.push(err),
+                },
+                Ok(None) => {}
+                Err(err) => errors.push(err),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // an enum without a rule gets one allowing the values the file gives it
+        let mut enum_vals: HashMap<&str, HashSet<String>> = HashMap::new();
+        for (entry, value) in &entries {
+            if entry.is_enum && self.rule_for(&entry.key).is_none() {
+                enum_vals.entry(entry.key.as_str()).or_default().insert(value.to_string());
+            }
+        }
+        let mut enum_rules: Vec<(String, ParamRule)> = enum_vals
+            .into_iter()
+            .map(|(key, enum_vals)| {
+                (key.to_string(), ParamRule {
+                    range: ParamRange::RangeEnum,
+                    enum_vals,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        enum_rules.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, rule) in enum_rules {
+            self.set_parameter_rule(&key, rule);
+        }
+
+        let mut count = 0;
+        for (entry, value) in entries {
+            if self.rule_for(&entry.key).is_none() {
+                self.set_parameter_rule(&entry.key, ParamRule {
+                    param_type: entry.value.param_type(),
+                    ..Default::default()
+                });
+            }
+            // the value went through the rule and the veto listeners when checked
+            if (override_existing || !self.params.contains_key(&entry.key)) && self.check_access(&entry.key, None).is_none() {
+                if self.params.get(&entry.key) != Some(&value) {
+                    self.store_value(&entry.key, value);
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // Returns the value to store.
+    fn check_typed_entry(&self, entry: &TypedEntry) -> Result<ParamValue, ParamError> {
+        let error = |message: String| ParamError::Parse {
+            line: entry.line,
+            message,
+        };
+        if let Some(rule) = self.rule_for(&entry.key) {
+            if entry.is_enum != matches!(rule.range, ParamRange::RangeEnum) {
+                return Err(error(format!("Enum declaration of {} does not match its rule", entry.key)));
+            }
+            if rule.param_type != entry.value.param_type() {
+                return Err(error(format!("{} is {} but its rule is {}", entry.key, entry.value.param_type().name(), rule.param_type.name())));
+            }
+        }
+        self.check_change(&entry.key, entry.value.clone())
+            .map(|(_, value)| value)
+            .map_err(|reason| error(format!("{} is rejected, {}", entry.value, reason)))
+    }
+}
@@ -0,0 +1,83 @@
+/*
+  Copyright (C) 2025 hidenorly
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::sync::Arc;
+
+use crate::listeners::IndexedListener;
+use crate::{ParamValue, ParameterManager, RejectReason};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeDecision {
+    Accept,
+    // store this value instead; it is checked against the key's rule again
+    Rewrite(ParamValue),
+    // the message comes back to the caller as RejectReason::Vetoed
+    Reject(String),
+}
+
+// Runs before a value is committed, with the manager as it is before the change; within a
+// transaction the other staged values are already in place. It must not lock the shared
+// manager, the caller already holds it.
+pub type VetoCallback = Arc<dyn Fn(&ParameterManager, &str, &ParamValue) -> ChangeDecision + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct VetoListener {
+    listener_id: usize,
+    callback: VetoCallback,
+}
+
+impl IndexedListener for VetoListener {
+    fn listener_id(&self) -> usize {
+        self.listener_id
+    }
+}
+
+impl ParameterManager {
+    // key takes the same patterns as register_callback. Veto listeners run in the order the
+    // listeners are notified, each one sees the value rewritten by the previous ones.
+    // unregister_callback removes them.
+    pub fn register_veto_callback<F>(&mut self, key: &str, callback: F) -> usize
+    where
+        F: Fn(&ParameterManager, &str, &ParamValue) -> ChangeDecision + Send + Sync + 'static,
+    {
+        let listener_id = self.listener_id;
+        self.listener_id += 1;
+        self.veto_listeners.insert(key, VetoListener {
+            listener_id,
+            callback: Arc::new(callback),
+        });
+        listener_id
+    }
+
+    // The rule check followed by the veto listeners. Returns the requested value coerced into
+    // the rule's type and the value to store.
+    pub(crate) fn check_change(&self, key: &str, value: ParamValue) -> Result<(ParamValue, ParamValue), RejectReason> {
+        self.check_change_in(self, key, value)
+    }
+
+    // view is the manager handed to the veto listeners.
+    pub(crate) fn check_change_in(&self, view: &ParameterManager, key: &str, value: ParamValue) -> Result<(ParamValue, ParamValue), RejectReason> {
+        let (requested, mut value) = self.check_value_with_rule(key, value)?;
+        for listener in self.veto_listeners.matching(key) {
+            match (listener.callback)(view, key, &value) {
+                ChangeDecision::Accept => {}
+                ChangeDecision::Rewrite(rewritten) => value = self.check_value_with_rule(key, rewritten)?.1,
+                ChangeDecision::Reject(message) => return Err(RejectReason::Vetoed(message)),
+            }
+        }
+        Ok((requested, value))
+    }
+}
@@ -15,15 +15,19 @@
 */
 
 use mockall::{mock, predicate::eq};
-use datamanager::{ParameterManager, ParamRule, ParamType, ParamRange};
+use regex::Regex;
+use datamanager::{read_journal, JournalOptions, ParameterManager, ParamRule, ParamType, ParamRange, ParamValue, ParamError, ParamDiff, ParamChange, ParamClient, ParamServer, OverflowPolicy, SubscribeOptions, ParamMetadata, Visibility, AccessMode, AccessPolicy, ChangeDecision, DispatchMode, PersistOptions, PersistPolicy, RejectReason, Revalidation, Schema, SetOutcome};
 
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
     use std::io::{Cursor};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
-    use std::fs::{File, OpenOptions};
+    use std::fs::File;
     use std::io::{BufReader, BufWriter, Write};
     use tempfile::tempdir;
 
@@ -52,7 +56,7 @@ mod tests {
 
         p_params.set_parameter("paramB", true);
         assert_eq!(p_params.get_parameter::<String, &str>("paramB", "false"), "true");
-        assert_eq!(p_params.get_parameter::<bool, bool>("paramB", false), true);
+        assert!(p_params.get_parameter::<bool, bool>("paramB", false));
 
         p_params.set_parameter("paramC", 1);
         assert_eq!(p_params.get_parameter::<i32, i32>("paramC", 0), 1);
@@ -61,15 +65,17 @@ mod tests {
         let int_value:i32 = p_params.get_parameter("paramC", 0);
         assert_eq!(int_value, 1);
 
-        let int_value2:i32 = p_params.get_parameter_int("paramC", 0);
+        let int_value2:i32 = p_params.get_parameter_int("paramC", 0).unwrap();
         assert_eq!(int_value2, 1);
+        assert_eq!(p_params.get_parameter_value("paramC"), Some(&ParamValue::Int(1)));
 
         // check helper func
         p_params.set_parameter("paramE", "1.5");
         assert_eq!(p_params.get_parameter_string("paramE", ""), "1.5");
-        assert_eq!(p_params.get_parameter_int("paramE", 0), 1);
-        assert_eq!(p_params.get_parameter_float("paramE", 0.0), 1.5);
-        assert_eq!(p_params.get_parameter_bool("paramE", true), false);
+        assert!(p_params.get_parameter_int("paramE", 0).is_err()); // "1.5" is not silently read as 1
+        assert_eq!(p_params.get_parameter_float("paramE", 0.0), Ok(1.5));
+        assert!(p_params.get_parameter_bool("paramE", true).is_err());
+        assert_eq!(p_params.get_parameter_int("paramNone", 5), Ok(5));
 
 
         // read only
@@ -86,7 +92,7 @@ mod tests {
 
     #[test]
     fn test_callback() {
-        let binding = ParameterManager::get_manager();
+        let binding = ParameterManager::get_manager_named("test_callback");
         let mut p_params = binding.lock().unwrap();
 
         let mut mock_my_callback = MockMyCallback::new();
@@ -106,6 +112,77 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_shared_manager() {
+        assert!(Arc::ptr_eq(&ParameterManager::get_manager(), &ParameterManager::get_manager()));
+
+        let audio = ParameterManager::get_manager_named("test_shared_audio");
+        let video = ParameterManager::get_manager_named("test_shared_video");
+        assert!(!Arc::ptr_eq(&audio, &video));
+
+        audio.lock().unwrap().set_parameter("volume", 10);
+        assert_eq!(
+            ParameterManager::get_manager_named("test_shared_audio").lock().unwrap().get_parameter_int("volume", 0),
+            Ok(10)
+        );
+        assert_eq!(video.lock().unwrap().get_parameter_int("volume", 0), Ok(0));
+
+        ParameterManager::reset_manager_named("test_shared_audio");
+        assert!(audio.lock().unwrap().get_parameter_value("volume").is_none());
+    }
+
+    #[test]
+    fn test_unregister_callback() {
+        let mut manager = ParameterManager::new();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let counter = count.clone();
+        let exact_id = manager.register_callback("audio.volume", move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = count.clone();
+        let wild_card_id = manager.register_callback("audio.*", move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        manager.set_parameter("audio.volume", 1);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        assert!(manager.unregister_callback(exact_id));
+        manager.set_parameter("audio.volume", 2);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        assert!(manager.unregister_callback(wild_card_id));
+        manager.set_parameter("audio.volume", 3);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        assert!(!manager.unregister_callback(wild_card_id));
+    }
+
+    #[test]
+    fn test_subscription() {
+        let manager = Arc::new(Mutex::new(ParameterManager::new()));
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let counter = count.clone();
+        let subscription = ParameterManager::register_subscription(&manager, "video.*", move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = count.clone();
+        let detached = ParameterManager::register_subscription(&manager, "video.width", move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .detach();
+        assert!(detached.is_some());
+
+        manager.lock().unwrap().set_parameter("video.width", 640);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        drop(subscription);
+        manager.lock().unwrap().set_parameter("video.width", 1280);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
     #[test]
     fn test_rule_int() {
         let mut manager = ParameterManager::new();
@@ -115,6 +192,7 @@ mod tests {
             range_min: 1.0,
             range_max: 10.0,
             enum_vals: HashSet::new(),
+            ..Default::default()
         };
         
         manager.set_parameter_rule("example", rule.clone());
@@ -126,21 +204,21 @@ mod tests {
         }
 
         manager.set_parameter("example", "1");
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
 
         // illegal case then the request should be clamped
         manager.set_parameter("example", "0");
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
         manager.set_parameter("example", 0);
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
         manager.set_parameter("example", 11);
-        assert_eq!(manager.get_parameter_int("example", 0), 10);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(10));
 
         manager.set_parameter("example", 1.0);
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
 
         manager.set_parameter("example", 11.0);
-        assert_eq!(manager.get_parameter_int("example", 0), 10);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(10));
     }
 
     #[test]
@@ -152,6 +230,7 @@ mod tests {
             range_min: -1.0,
             range_max: 1.0,
             enum_vals: HashSet::new(),
+            ..Default::default()
         };
         
         manager.set_parameter_rule("example", rule.clone());
@@ -163,15 +242,15 @@ mod tests {
         }
 
         manager.set_parameter("example", 1.0);
-        assert_eq!(manager.get_parameter_bool("example", true), false);
+        assert!(manager.get_parameter_bool("example", true).is_err());
         assert_eq!(manager.get_parameter_string("example", ""), "1"); // Note the return is "1". not "1.0"
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
-        assert_eq!(manager.get_parameter_float("example", 0.0), 1.0);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
+        assert_eq!(manager.get_parameter_float("example", 0.0), Ok(1.0));
 
         manager.set_parameter("example", 1.1);
-        assert_eq!(manager.get_parameter_float("example", 0.0), 1.0);
+        assert_eq!(manager.get_parameter_float("example", 0.0), Ok(1.0));
         manager.set_parameter("example", -1.1);
-        assert_eq!(manager.get_parameter_float("example", 0.0), -1.0);
+        assert_eq!(manager.get_parameter_float("example", 0.0), Ok(-1.0));
     }
 
     #[test]
@@ -183,6 +262,7 @@ mod tests {
             range_min: -1.0,
             range_max: 0.0,
             enum_vals: HashSet::new(),
+            ..Default::default()
         };
         
         manager.set_parameter_rule("example", rule.clone());
@@ -194,16 +274,23 @@ mod tests {
         }
 
         manager.set_parameter("example", "true");
-        assert_eq!(manager.get_parameter_bool("example", false), true);
+        assert_eq!(manager.get_parameter_bool("example", false), Ok(true));
         assert_eq!(manager.get_parameter_string("example", "false"), "true");
-        assert_eq!(manager.get_parameter_int("example", 0), 0); // int("true")==0
-        assert_eq!(manager.get_parameter_float("example", 0.0), 0.0); // int("true")==0
+        assert_eq!(
+            manager.get_parameter_int("example", 0),
+            Err(ParamError::TypeMismatch {
+                key: "example".to_string(),
+                expected: ParamType::TypeInt,
+                actual: ParamType::TypeBool,
+            })
+        );
+        assert!(manager.get_parameter_float("example", 0.0).is_err());
 
         manager.set_parameter("example", "false");
-        assert_eq!(manager.get_parameter_bool("example", true), false);
+        assert_eq!(manager.get_parameter_bool("example", true), Ok(false));
         assert_eq!(manager.get_parameter_string("example", "true"), "false");
-        assert_eq!(manager.get_parameter_int("example", 0), 0); // float("true")==0
-        assert_eq!(manager.get_parameter_float("example", 0.0), 0.0); // int("true")==0
+        assert!(manager.get_parameter_int("example", 0).is_err());
+        assert!(manager.get_parameter_float("example", 0.0).is_err());
     }
 
     #[test]
@@ -215,6 +302,7 @@ mod tests {
             range_min: 0.0,
             range_max: 0.0,
             enum_vals: HashSet::new(),
+            ..Default::default()
         };
         
         manager.set_parameter_rule("example", rule.clone());
@@ -227,19 +315,19 @@ mod tests {
 
         manager.set_parameter("example", "low");
         assert_eq!(manager.get_parameter_string("example", ""), "low");
-        assert_eq!(manager.get_parameter_int("example", 0), 0);
-        assert_eq!(manager.get_parameter_float("example", 0.0), 0.0);
-        assert_eq!(manager.get_parameter_bool("example", true), false);
+        assert!(manager.get_parameter_int("example", 0).is_err());
+        assert!(manager.get_parameter_float("example", 0.0).is_err());
+        assert!(manager.get_parameter_bool("example", true).is_err());
 
         manager.set_parameter("example", "1.0");
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
-        assert_eq!(manager.get_parameter_float("example", 0.0), 1.0);
-        assert_eq!(manager.get_parameter_bool("example", true), false);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
+        assert_eq!(manager.get_parameter_float("example", 0.0), Ok(1.0));
+        assert!(manager.get_parameter_bool("example", true).is_err());
 
         manager.set_parameter("example", "1");
-        assert_eq!(manager.get_parameter_int("example", 0), 1);
-        assert_eq!(manager.get_parameter_float("example", 0.0), 1.0);
-        assert_eq!(manager.get_parameter_bool("example", true), false);
+        assert_eq!(manager.get_parameter_int("example", 0), Ok(1));
+        assert_eq!(manager.get_parameter_float("example", 0.0), Ok(1.0));
+        assert!(manager.get_parameter_bool("example", true).is_err());
     }
 
     #[test]
@@ -251,6 +339,7 @@ mod tests {
             range_min: 0.0,
             range_max: 0.0,
             enum_vals: ["low", "mid", "high"].iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
         };
         
         manager.set_parameter_rule("example", rule.clone());
@@ -274,6 +363,252 @@ mod tests {
         assert_eq!(manager.get_parameter_string("example", ""), "high");
     }
 
+    #[test]
+    fn test_try_set_parameter() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("volume", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::Ranged,
+            range_min: 0.0,
+            range_max: 10.0,
+            ..Default::default()
+        });
+        manager.set_parameter_rule("mode", ParamRule {
+            param_type: ParamType::TypeString,
+            range: ParamRange::RangeEnum,
+            enum_vals: ["low", "high"].iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        });
+
+        assert_eq!(manager.try_set_parameter("volume", 5), SetOutcome::Stored);
+        assert_eq!(manager.try_set_parameter("volume", "5"), SetOutcome::Unchanged);
+        assert_eq!(
+            manager.try_set_parameter("volume", 11),
+            SetOutcome::Clamped { from: ParamValue::Int(11), to: ParamValue::Int(10) }
+        );
+        assert_eq!(
+            manager.try_set_parameter("volume", true),
+            SetOutcome::Rejected {
+                reason: RejectReason::TypeMismatch { expected: ParamType::TypeInt, actual: ParamType::TypeBool },
+            }
+        );
+        assert_eq!(
+            manager.try_set_parameter("mode", "mid"),
+            SetOutcome::Rejected { reason: RejectReason::NotInEnum { allowed: vec!["high".to_string(), "low".to_string()] } }
+        );
+        assert_eq!(manager.get_parameter_int("volume", 0), Ok(10));
+
+        assert_eq!(manager.try_set_parameter("ro.serial", "A01"), SetOutcome::Stored);
+        assert_eq!(manager.try_set_parameter("ro.serial", "A01"), SetOutcome::Unchanged);
+        assert_eq!(manager.try_set_parameter("ro.serial", "B02"), SetOutcome::ReadOnly);
+        assert!(!SetOutcome::ReadOnly.is_accepted());
+
+        // ranges holding no value of the type reject instead of panicking
+        let empty_ranges = [
+            (ParamType::TypeInt, ParamRange::Ranged, 0.5, 0.7),
+            (ParamType::TypeInt, ParamRange::Ranged, f64::NAN, 10.0),
+            (ParamType::TypeFloat, ParamRange::Ranged, 1.5, 0.5),
+            (ParamType::TypeFloat, ParamRange::Ranged, 0.0, f64::NAN),
+            (ParamType::TypeInt, ParamRange::RangedInt { min: 5, max: 0 }, 0.0, 0.0),
+        ];
+        for (param_type, range, range_min, range_max) in empty_ranges {
+            manager.set_parameter_rule("empty", ParamRule {
+                param_type,
+                range,
+                range_min,
+                range_max,
+                ..Default::default()
+            });
+            assert!(matches!(
+                manager.try_set_parameter("empty", 3),
+                SetOutcome::Rejected { reason: RejectReason::EmptyRange { .. } }
+            ));
+        }
+        assert!(manager.get_parameter_value("empty").is_none());
+    }
+
+    #[test]
+    fn test_rule_constraints() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("user.name", ParamRule {
+            param_type: ParamType::TypeString,
+            pattern: Some(Regex::new("^[a-z][a-z0-9_]*$").unwrap()),
+            min_length: Some(3),
+            max_length: Some(8),
+            validators: vec![Arc::new(|value: &ParamValue| {
+                if value.to_string() == "root" { Err("reserved name".to_string()) } else { Ok(()) }
+            })],
+            ..Default::default()
+        });
+        manager.set_parameter_rule("audio.samplerate", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::Ranged,
+            range_min: 8000.0,
+            range_max: 48000.0,
+            step: Some(8000.0),
+            ..Default::default()
+        });
+
+        assert_eq!(manager.try_set_parameter("user.name", "alice"), SetOutcome::Stored);
+        assert_eq!(
+            manager.try_set_parameter("user.name", "Alice"),
+            SetOutcome::Rejected { reason: RejectReason::PatternMismatch { pattern: "^[a-z][a-z0-9_]*$".to_string() } }
+        );
+        assert_eq!(
+            manager.try_set_parameter("user.name", "al"),
+            SetOutcome::Rejected { reason: RejectReason::TooShort { min_length: 3 } }
+        );
+        assert_eq!(
+            manager.try_set_parameter("user.name", "alice_and_bob"),
+            SetOutcome::Rejected { reason: RejectReason::TooLong { max_length: 8 } }
+        );
+        assert_eq!(
+            manager.try_set_parameter("user.name", "root"),
+            SetOutcome::Rejected { reason: RejectReason::Invalid("reserved name".to_string()) }
+        );
+        assert_eq!(manager.get_parameter_string("user.name", ""), "alice");
+
+        assert_eq!(manager.try_set_parameter("audio.samplerate", 16000), SetOutcome::Stored);
+        assert_eq!(
+            manager.try_set_parameter("audio.samplerate", 22050),
+            SetOutcome::Clamped { from: ParamValue::Int(22050), to: ParamValue::Int(24000) }
+        );
+        assert_eq!(
+            manager.try_set_parameter("audio.samplerate", 96000),
+            SetOutcome::Clamped { from: ParamValue::Int(96000), to: ParamValue::Int(48000) }
+        );
+    }
+
+    #[test]
+    fn test_set_parameter_rule_revalidates() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter("audio.volume", 150);
+        manager.set_parameter("audio.mode", "mid");
+        manager.set_parameter("audio.eq.bass", "3");
+        manager.set_parameter("audio.eq.name", "flat");
+
+        let changed = Arc::new(Mutex::new(Vec::new()));
+        let changed_values = changed.clone();
+        manager.register_callback("audio.*", move |key, value| changed_values.lock().unwrap().push(format!("{}={}", key, value)));
+
+        let report = manager.set_parameter_rule("audio.volume", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::RangedInt { min: 0, max: 100 },
+            ..Default::default()
+        });
+        assert_eq!(report, vec![Revalidation::Adjusted {
+            key: "audio.volume".to_string(),
+            from: ParamValue::Int(150),
+            to: ParamValue::Int(100),
+        }]);
+
+        let report = manager.set_parameter_rule("audio.mode", ParamRule {
+            param_type: ParamType::TypeString,
+            range: ParamRange::RangeEnum,
+            enum_vals: ["low", "high"].iter().map(|s| s.to_string()).collect(),
+            default_value: Some(ParamValue::String("low".to_string())),
+            ..Default::default()
+        });
+        assert!(matches!(&report[..], [Revalidation::Reset { to: ParamValue::String(to), .. }] if to == "low"));
+
+        let report = manager.set_parameter_rule("audio.eq.*", ParamRule {
+            param_type: ParamType::TypeInt,
+            ..Default::default()
+        });
+        assert_eq!(report.len(), 2);
+        assert!(matches!(&report[0], Revalidation::Adjusted { key, to: ParamValue::Int(3), .. } if key == "audio.eq.bass"));
+        assert!(matches!(&report[1], Revalidation::Invalid { key, .. } if key == "audio.eq.name"));
+        assert_eq!(manager.get_parameter_string("audio.eq.name", ""), "flat");
+
+        assert_eq!(
+            *changed.lock().unwrap(),
+            vec!["audio.volume=100".to_string(), "audio.mode=low".to_string(), "audio.eq.bass=3".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_typed_value() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter("float", 0.1_f64);
+        assert_eq!(manager.get_parameter_value("float"), Some(&ParamValue::Float(0.1)));
+        assert!(manager.get_parameter_int("float", 0).is_err());
+
+        manager.set_parameter("big", 1_i64 << 40);
+        assert_eq!(manager.get_parameter_value("big"), Some(&ParamValue::Int(1 << 40)));
+        assert!(matches!(manager.get_parameter_int("big", 0), Err(ParamError::OutOfRange { .. })));
+
+        let rule = ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::RangeAny,
+            range_min: 0.0,
+            range_max: 0.0,
+            enum_vals: HashSet::new(),
+            ..Default::default()
+        };
+        manager.set_parameter_rule("int", rule);
+        manager.set_parameter("int", "3");
+        assert_eq!(manager.get_parameter_value("int"), Some(&ParamValue::Int(3)));
+
+        // "3.9" cannot be stored as an int without losing precision, so it is rejected
+        manager.set_parameter("int", "3.9");
+        assert_eq!(manager.get_parameter_value("int"), Some(&ParamValue::Int(3)));
+        manager.set_parameter("int", true);
+        assert_eq!(manager.get_parameter_value("int"), Some(&ParamValue::Int(3)));
+    }
+
+    #[test]
+    fn test_64bit_values() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("timestamp", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::RangedInt { min: 0, max: i64::MAX - 1 },
+            ..Default::default()
+        });
+        manager.set_parameter_rule("bytes", ParamRule {
+            param_type: ParamType::TypeUInt,
+            range: ParamRange::RangedUInt { min: 1, max: u64::MAX - 1 },
+            step: Some(2.0),
+            ..Default::default()
+        });
+
+        manager.set_parameter("timestamp", (1_i64 << 53) + 1);
+        assert_eq!(manager.get_parameter_i64("timestamp", 0), Ok((1 << 53) + 1));
+        manager.set_parameter("timestamp", i64::MAX);
+        assert_eq!(manager.get_parameter_i64("timestamp", 0), Ok(i64::MAX - 1));
+
+        manager.set_parameter("bytes", "18446744073709551613");
+        assert_eq!(manager.get_parameter_u64("bytes", 0), Ok(u64::MAX - 2));
+        assert_eq!(manager.get_parameter_value("bytes"), Some(&ParamValue::UInt(u64::MAX - 2)));
+        assert!(manager.get_parameter_i64("bytes", 0).is_err());
+
+        manager.set_parameter("ratio", 0.1_f64 + 0.2_f64);
+        assert_eq!(manager.get_parameter_f64("ratio", 0.0), Ok(0.1_f64 + 0.2_f64));
+
+        // huge steps round to the nearest multiple without overflowing
+        for (step, value, expected) in [(1e300, 5, 0), (u64::MAX as f64, i64::MIN, 0), (1e19, i64::MAX, i64::MAX)] {
+            manager.set_parameter_rule("coarse", ParamRule {
+                param_type: ParamType::TypeInt,
+                step: Some(step),
+                ..Default::default()
+            });
+            manager.set_parameter("coarse", value);
+            assert_eq!(manager.get_parameter_i64("coarse", -1), Ok(expected));
+        }
+
+        let mut output = Vec::new();
+        manager.store_to_typed_stream(&mut output).expect("Failed to store");
+        let mut restored = ParameterManager::new();
+        restored.restore_from_typed_stream(&mut BufReader::new(Cursor::new(output)), true).expect("Failed to restore");
+        assert_eq!(restored.get_parameter_u64("bytes", 0), Ok(u64::MAX - 2));
+        assert_eq!(restored.get_parameter_i64("timestamp", 0), Ok(i64::MAX - 1));
+        assert_eq!(restored.get_parameter_f64("ratio", 0.0), Ok(0.1_f64 + 0.2_f64));
+
+        let mut restored = ParameterManager::new();
+        restored.restore_from_json(&mut Cursor::new(manager.to_json(true)), true).expect("Failed to restore");
+        assert_eq!(restored.get_parameter_u64("bytes", 0), Ok(u64::MAX - 2));
+        assert_eq!(restored.get_parameter_rule("timestamp").range, ParamRange::RangedInt { min: 0, max: i64::MAX - 1 });
+    }
+
     #[test]
     fn test_store_to_stream() {
         let mut manager = ParameterManager::new();
@@ -363,7 +698,7 @@ mod tests {
         assert!(result, "restore_from_stream should return true if at least one line is valid");
 
         assert!(
-            manager.get_parameter_string("key1", "") == "",
+            manager.get_parameter_string("key1", "").is_empty(),
             "Malformed key1 should not be added"
         );
         assert_eq!(
@@ -425,5 +760,1204 @@ mod tests {
         assert_eq!(manager.get_parameter_string("key1", ""), "old_value");
         assert_eq!(manager.get_parameter_string("key2", ""), "value2");
     }
-}
 
+    #[test]
+    fn test_typed_stream_round_trip() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("mode", ParamRule {
+            param_type: ParamType::TypeString,
+            range: ParamRange::RangeEnum,
+            range_min: 0.0,
+            range_max: 0.0,
+            enum_vals: ["low", "high"].iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        });
+        manager.set_parameter("mode", "high");
+        manager.set_parameter("count", 3);
+        manager.set_parameter("ratio", 0.25);
+        manager.set_parameter("enabled", true);
+        manager.set_parameter("name", "say \"hi\"\nbye");
+
+        let mut output = Vec::new();
+        manager.store_to_typed_stream(&mut output).expect("Failed to store");
+        let output_str = String::from_utf8(output.clone()).unwrap();
+        assert_eq!(
+            output_str,
+            "count = Int(3)\nenabled = Bool(true)\nmode = Enum(high)\nname = String(\"say \\\"hi\\\"\\nbye\")\nratio = Float(0.25)\n"
+        );
+
+        let mut restored = ParameterManager::new();
+        restored.set_parameter_rule("mode", manager.get_parameter_rule("mode"));
+        let mut reader = BufReader::new(Cursor::new(output));
+        assert_eq!(restored.restore_from_typed_stream(&mut reader, true), Ok(5));
+        assert_eq!(restored.get_parameter_value("count"), Some(&ParamValue::Int(3)));
+        assert_eq!(restored.get_parameter_value("ratio"), Some(&ParamValue::Float(0.25)));
+        assert_eq!(restored.get_parameter_bool("enabled", false), Ok(true));
+        assert_eq!(restored.get_parameter_string("name", ""), "say \"hi\"\nbye");
+        assert_eq!(restored.get_parameter_string("mode", ""), "high");
+
+        // the declared type becomes the rule of the key
+        assert_eq!(restored.get_parameter_rule("count").param_type, ParamType::TypeInt);
+
+        // without a rule an Enum value loads with an enum rule allowing it
+        let mut fresh = ParameterManager::new();
+        let mut reader = BufReader::new(Cursor::new(output_str.clone()));
+        assert_eq!(fresh.restore_from_typed_stream(&mut reader, true), Ok(5));
+        assert_eq!(fresh.get_parameter_string("mode", ""), "high");
+        assert_eq!(fresh.get_parameter_rule("mode").range, ParamRange::RangeEnum);
+        let mut round_trip = Vec::new();
+        fresh.store_to_typed_stream(&mut round_trip).expect("Failed to store");
+        assert_eq!(String::from_utf8(round_trip).unwrap(), output_str);
+    }
+
+    #[test]
+    fn test_restore_from_typed_file() {
+        let file_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_params.txt");
+        let mut reader = BufReader::new(File::open(file_path).expect("Failed to open file"));
+
+        let mut manager = ParameterManager::new();
+        assert_eq!(manager.restore_from_typed_stream(&mut reader, true), Ok(1));
+        assert_eq!(manager.get_parameter_rule("paramX").param_type, ParamType::TypeFloat);
+        assert_eq!(manager.get_parameter_string("paramX", ""), "3.14");
+    }
+
+    #[test]
+    fn test_restore_from_typed_stream_errors() {
+        let input_data = "# comment\n\nkey1 = Int(1)\nkey2 Int(2)\nkey3 = Int(abc)\nkey4 = Float(1.5)\nkey5 = Enum(low)\n";
+        let mut reader = BufReader::new(Cursor::new(input_data.as_bytes()));
+
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("key4", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::RangeAny,
+            range_min: 0.0,
+            range_max: 0.0,
+            enum_vals: HashSet::new(),
+            ..Default::default()
+        });
+
+        let errors = manager.restore_from_typed_stream(&mut reader, true).unwrap_err();
+        let lines: Vec<usize> = errors
+            .iter()
+            .map(|err| match err {
+                ParamError::Parse { line, .. } => *line,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(lines, vec![4, 5, 6]);
+        assert!(errors[2].to_string().contains("key4 is Float but its rule is Int"), "{}", errors[2]);
+
+        // nothing is applied when the stream has errors
+        assert!(manager.get_parameter_value("key1").is_none());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("audio.volume", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::Ranged,
+            range_min: 0.0,
+            range_max: 100.0,
+            enum_vals: HashSet::new(),
+            ..Default::default()
+        });
+        manager.set_parameter("audio.volume", 50);
+        manager.set_parameter("audio", "on");
+        manager.set_parameter("audio.gain", 1.0);
+        manager.set_parameter("audio.mute", false);
+        manager.set_parameter("name", "quote\" colon\":\" newline\n end");
+
+        let mut output = Vec::new();
+        manager.store_to_json(&mut output, true).expect("Failed to store");
+        let output_str = String::from_utf8(output.clone()).unwrap();
+        assert!(output_str.contains("\"volume\": 50"), "Unexpected output: {}", output_str);
+
+        let mut restored = ParameterManager::new();
+        assert_eq!(restored.restore_from_json(&mut Cursor::new(output), true), Ok(5));
+        assert_eq!(restored.get_parameter_value("audio.volume"), Some(&ParamValue::Int(50)));
+        assert_eq!(restored.get_parameter_value("audio.gain"), Some(&ParamValue::Float(1.0)));
+        assert_eq!(restored.get_parameter_value("audio.mute"), Some(&ParamValue::Bool(false)));
+        assert_eq!(restored.get_parameter_string("audio", ""), "on");
+        assert_eq!(restored.get_parameter_string("name", ""), "quote\" colon\":\" newline\n end");
+
+        // the rule came along with the values
+        restored.set_parameter("audio.volume", 1000);
+        assert_eq!(restored.get_parameter_int("audio.volume", 0), Ok(100));
+
+        // empty segments do not collide with the parent's own value
+        let keys = ["audio", "audio.", "audio..", "a..b", "a.b", ".x", "", "."];
+        let mut manager = ParameterManager::new();
+        for (index, key) in keys.iter().enumerate() {
+            manager.set_parameter(key, index as i64);
+        }
+        let mut restored = ParameterManager::new();
+        assert_eq!(restored.restore_from_json(&mut Cursor::new(manager.to_json(false)), true), Ok(keys.len()));
+        for (index, key) in keys.iter().enumerate() {
+            assert_eq!(restored.get_parameter_value(key), Some(&ParamValue::Int(index as i64)), "{}", key);
+        }
+        assert_eq!(restored.keys().len(), keys.len());
+    }
+
+    #[test]
+    fn test_restore_from_json_errors() {
+        let mut manager = ParameterManager::new();
+        let result = manager.restore_from_json(&mut Cursor::new("{\n  \"params\": {\n    \"a\": [1]\n"), true);
+        assert!(matches!(result.as_ref().map_err(Vec::as_slice), Err([ParamError::Parse { line: 4, .. }])), "{:?}", result);
+
+        let result = manager.restore_from_json(&mut Cursor::new("{\"params\": {\"a\": [1]}}"), true);
+        assert!(matches!(result.as_ref().map_err(Vec::as_slice), Err([ParamError::Parse { .. }])));
+        assert!(manager.get_parameter_value("a").is_none());
+
+        // a refused value fails the whole document, its rules included
+        let document = r#"{
+            "params": { "mode": "mid", "volume": 5 },
+            "rules": { "mode": { "type": "String", "enum": ["low", "high"] } }
+        }"#;
+        let result = manager.restore_from_json(&mut Cursor::new(document), true);
+        assert!(matches!(result.as_ref().map_err(Vec::as_slice), Err([ParamError::Rejected { key, reason: RejectReason::NotInEnum { .. }, .. }]) if key == "mode"), "{:?}", result);
+        assert!(manager.get_parameter_value("volume").is_none());
+        assert_eq!(manager.get_parameter_rule("mode").range, ParamRange::RangeAny);
+
+        // only the values actually stored are counted
+        manager.set_parameter("volume", 5);
+        let document = r#"{ "params": { "mode": "high", "volume": 5 } }"#;
+        assert_eq!(manager.restore_from_json(&mut Cursor::new(document), true), Ok(1));
+    }
+
+    #[test]
+    fn test_bind_file() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let file_path = dir.path().join("params.txt");
+        std::fs::write(&file_path, "persist.volume = Int(3)\n").expect("Failed to write");
+
+        let mut manager = ParameterManager::new();
+        let options = PersistOptions {
+            debounce: Duration::ZERO,
+            policy: PersistPolicy::Prefixes(vec!["persist.".to_string()]),
+        };
+        assert_eq!(manager.bind_file_with_options(&file_path, options), Ok(1));
+        assert_eq!(manager.get_parameter_int("persist.volume", 0), Ok(3));
+
+        manager.set_parameter("persist.volume", 5);
+        manager.set_parameter("volatile.volume", 7);
+        let contents = std::fs::read_to_string(&file_path).expect("Failed to read");
+        assert_eq!(contents, "persist.volume = Int(5)\n");
+
+        // only the target file is left, no temp files
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_bind_file_debounce() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let file_path = dir.path().join("params.json");
+
+        let mut manager = ParameterManager::new();
+        let options = PersistOptions {
+            debounce: Duration::from_secs(60),
+            policy: PersistPolicy::All,
+        };
+        assert_eq!(manager.bind_file_with_options(&file_path, options), Ok(0));
+        for i in 0..10 {
+            manager.set_parameter("audio.volume", i);
+        }
+        // the burst is still waiting for the debounce period
+        assert!(!file_path.exists());
+
+        assert_eq!(manager.unbind_file(), Ok(()));
+        let mut restored = ParameterManager::new();
+        assert_eq!(restored.restore_from_file(&file_path, true), Ok(1));
+        assert_eq!(restored.get_parameter_int("audio.volume", 0), Ok(9));
+
+        // the written file keeps the bound values and follows removals and later rules
+        let file_path = dir.path().join("params.txt");
+        std::fs::write(&file_path, "eq.bass = Int(1)\neq.treble = Int(2)\nmode = String(low)\n").expect("Failed to write");
+        let mut manager = ParameterManager::new();
+        let options = PersistOptions {
+            debounce: Duration::from_secs(60),
+            policy: PersistPolicy::All,
+        };
+        assert_eq!(manager.bind_file_with_options(&file_path, options), Ok(3));
+        manager.set_parameter_rule("mode", ParamRule {
+            param_type: ParamType::TypeString,
+            range: ParamRange::RangeEnum,
+            enum_vals: ["low", "high"].iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        });
+        for i in 0..100 {
+            manager.set_parameter("eq.bass", i);
+        }
+        manager.set_parameter("mode", "high");
+        manager.remove_subtree("eq.treble");
+        assert_eq!(manager.flush(), Ok(()));
+        let contents = std::fs::read_to_string(&file_path).expect("Failed to read");
+        assert_eq!(contents, "eq.bass = Int(99)\nmode = Enum(high)\n");
+        assert_eq!(manager.unbind_file(), Ok(()));
+    }
+
+    #[test]
+    fn test_reload_from_file() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let file_path = dir.path().join("params.txt");
+
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("mode", ParamRule {
+            param_type: ParamType::TypeString,
+            range: ParamRange::RangeEnum,
+            range_min: 0.0,
+            range_max: 0.0,
+            enum_vals: ["low", "high"].iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        });
+        manager.set_parameter("volume", 1);
+        manager.set_parameter("gain", 2);
+        manager.set_parameter("ro.serial", "abc");
+
+        let changed = Arc::new(Mutex::new(Vec::new()));
+        let changed_keys = changed.clone();
+        manager.register_callback("*", move |key, _| changed_keys.lock().unwrap().push(key));
+
+        std::fs::write(&file_path, "volume = Int(5)\ngain = Int(2)\nmode = Enum(off)\nro.serial = String(xyz)\n").expect("Failed to write");
+        let report = manager.reload_from_file(&file_path).expect("Failed to reload");
+
+        assert_eq!(report.changed, vec!["volume".to_string()]);
+        assert_eq!(
+            report.rejected,
+            vec![
+                ParamError::Rejected {
+                    key: "mode".to_string(),
+                    value: ParamValue::String("off".to_string()),
+                    reason: RejectReason::NotInEnum { allowed: vec!["high".to_string(), "low".to_string()] },
+                },
+                ParamError::ReadOnly { key: "ro.serial".to_string() },
+            ]
+        );
+        assert_eq!(*changed.lock().unwrap(), vec!["volume".to_string()]);
+        assert_eq!(manager.get_parameter_int("volume", 0), Ok(5));
+    }
+
+    #[test]
+    fn test_watch_file() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let file_path = dir.path().join("params.txt");
+        std::fs::write(&file_path, "volume = Int(1)\n").expect("Failed to write");
+
+        let manager = Arc::new(Mutex::new(ParameterManager::new()));
+        manager.lock().unwrap().restore_from_file(&file_path, true).expect("Failed to restore");
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let _watcher = ParameterManager::watch_file(&manager, &file_path, Duration::from_millis(10), move |result| {
+            sender.lock().unwrap().send(result).unwrap();
+        });
+
+        std::fs::write(&file_path, "volume = Int(2)\n").expect("Failed to write");
+        let report = receiver.recv_timeout(Duration::from_secs(5)).expect("No reload").expect("Failed to reload");
+        assert_eq!(report.changed, vec!["volume".to_string()]);
+        assert_eq!(manager.lock().unwrap().get_parameter_int("volume", 0), Ok(2));
+
+        // the manager's own writes to the bound file are not reloaded over newer values
+        let options = PersistOptions {
+            debounce: Duration::from_secs(60),
+            policy: PersistPolicy::All,
+        };
+        manager.lock().unwrap().bind_file_with_options(&file_path, options).expect("Failed to bind");
+        {
+            let mut manager = manager.lock().unwrap();
+            manager.set_parameter("volume", 3);
+            assert_eq!(manager.flush(), Ok(()));
+            manager.set_parameter("volume", 4);
+        }
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(manager.lock().unwrap().get_parameter_int("volume", 0), Ok(4));
+
+        // an edit meanwhile applies everything but the pending key; renamed into place so the
+        // watcher never reads it half written
+        let edit_path = dir.path().join("edit.tmp");
+        std::fs::write(&edit_path, "mode = String(high)\nvolume = Int(3)\n").expect("Failed to write");
+        std::fs::rename(&edit_path, &file_path).expect("Failed to rename");
+        let report = receiver.recv_timeout(Duration::from_secs(5)).expect("No reload").expect("Failed to reload");
+        assert_eq!(report.changed, vec!["mode".to_string()]);
+        assert_eq!(manager.lock().unwrap().get_parameter_int("volume", 0), Ok(4));
+        assert_eq!(manager.lock().unwrap().unbind_file(), Ok(()));
+        let contents = std::fs::read_to_string(&file_path).expect("Failed to read");
+        assert_eq!(contents, "mode = String(\"high\")\nvolume = Int(4)\n");
+    }
+
+    const TEST_SCHEMA: &str = r#"{
+        "rules": {
+            "audio.volume": { "type": "Int", "min": 0, "max": 100, "default": 50, "description": "Master volume" },
+            "audio.mode": { "type": "String", "enum": ["low", "high"], "default": "low" },
+            "audio.eq.*": { "type": "Float", "min": -12, "max": 12 },
+            "audio.serial": { "type": "String", "read_only": true, "default": "A01" }
+        }
+    }"#;
+
+    #[test]
+    fn test_apply_schema() {
+        let schema = Schema::from_reader(&mut Cursor::new(TEST_SCHEMA)).expect("Failed to load schema");
+        let rule = schema.rule_for("audio.volume").expect("No rule");
+        assert_eq!(rule.description, "Master volume");
+        assert_eq!(rule.default_value, Some(ParamValue::Int(50)));
+
+        let mut manager = ParameterManager::new();
+        manager.set_parameter("audio.mode", "high");
+        assert_eq!(manager.apply_schema(&schema), 2);
+        assert_eq!(manager.get_parameter_int("audio.volume", 0), Ok(50));
+        assert_eq!(manager.get_parameter_string("audio.mode", ""), "high");
+
+        // the wild card rule applies to every key under audio.eq.
+        manager.set_parameter("audio.eq.bass", 20);
+        assert_eq!(manager.get_parameter_value("audio.eq.bass"), Some(&ParamValue::Float(12.0)));
+
+        manager.set_parameter("audio.serial", "B02");
+        assert_eq!(manager.get_parameter_string("audio.serial", ""), "A01");
+
+        for rule in [r#"{"type":"Int","min":10,"max":0}"#, r#"{"type":"Float","min":1.5,"max":0.5}"#, r#"{"type":"UInt","min":3,"max":2}"#] {
+            let schema = format!(r#"{{"rules": {{"audio.volume": {}}}}}"#, rule);
+            let result = Schema::from_reader(&mut Cursor::new(schema));
+            assert!(matches!(result, Err(ParamError::Parse { message, .. }) if message.contains("min greater than max")));
+        }
+    }
+
+    #[test]
+    fn test_validate_file_with_schema() {
+        let schema = Schema::from_reader(&mut Cursor::new(TEST_SCHEMA)).expect("Failed to load schema");
+
+        let dir = tempdir().expect("Failed to create temp dir");
+        let file_path = dir.path().join("params.txt");
+        std::fs::write(
+            &file_path,
+            "audio.volume = Int(150)\naudio.mode = String(mid)\naudio.eq.bass = Float(3)\naudio.eq.treble = String(x)\nvideo.width = Int(640)\n",
+        )
+        .expect("Failed to write");
+
+        let violations = schema.validate_file(&file_path).expect("Failed to read file");
+        let keys: Vec<&str> = violations.iter().map(|v| v.key.as_str()).collect();
+        assert_eq!(keys, vec!["audio.volume", "audio.mode", "audio.eq.treble", "video.width"]);
+        assert_eq!(violations[0].message, "150 is out of range [0, 100]");
+    }
+
+    #[test]
+    fn test_reset_to_default() {
+        let mut param_manager = ParameterManager::new();
+        param_manager.set_parameter_rule("audio.volume", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::Ranged,
+            range_min: 0.0,
+            range_max: 100.0,
+            default_value: Some(ParamValue::Int(50)),
+            ..Default::default()
+        });
+        param_manager.set_parameter_rule("audio.mode", ParamRule {
+            default_value: Some(ParamValue::from("normal")),
+            ..Default::default()
+        });
+
+        // the rule's default wins over the call site's one
+        assert_eq!(param_manager.get_parameter_int("audio.volume", 10).unwrap(), 50);
+        assert_eq!(param_manager.get_parameter_int("audio.other", 10).unwrap(), 10);
+        assert!(param_manager.is_default("audio.volume"));
+
+        let (tx, rx) = mpsc::channel();
+        param_manager.register_callback("audio.*", move |key, value| {
+            tx.send((key, value)).unwrap();
+        });
+        param_manager.set_parameter("audio.volume", 80);
+        param_manager.set_parameter("audio.balance", 3);
+        assert!(!param_manager.is_default("audio.volume"));
+        assert!(!param_manager.is_default("audio.balance"));
+        assert_eq!(rx.try_iter().count(), 2);
+
+        let compact = param_manager.without_defaults().to_json(false);
+        assert!(compact.contains("\"volume\": 80"));
+
+        assert!(param_manager.reset_parameter("audio.volume"));
+        assert!(!param_manager.reset_parameter("audio.volume"));
+        assert_eq!(rx.try_recv().unwrap(), ("audio.volume".to_string(), "50".to_string()));
+        assert!(param_manager.is_default("audio.volume"));
+        assert!(!param_manager.without_defaults().to_json(false).contains("volume"));
+
+        // keys without default are removed, exact rules under the prefix get their default
+        assert_eq!(param_manager.reset_subtree("audio."), vec!["audio.balance", "audio.mode"]);
+        assert_eq!(param_manager.get_parameter_value("audio.balance"), None);
+        assert_eq!(param_manager.get_parameter_value("audio.mode"), Some(&ParamValue::from("normal")));
+        assert_eq!(rx.try_recv().unwrap(), ("audio.mode".to_string(), "normal".to_string()));
+
+        param_manager.set_parameter("audio.volume", 20);
+        param_manager.set_parameter("ro.serial", "abc");
+        assert_eq!(param_manager.reset_all(), vec!["audio.volume"]);
+        assert_eq!(param_manager.get_parameter_string("ro.serial", ""), "abc");
+    }
+
+    #[test]
+    fn test_access_policy() {
+        let mut manager = ParameterManager::new();
+        manager.set_access_policy("audio.*", AccessPolicy {
+            owner: Some("audio-service".to_string()),
+            ..Default::default()
+        });
+        // taking over from an owned policy needs the owner's token
+        let calibration = AccessPolicy {
+            mode: AccessMode::WriteOnce,
+            owner: Some("audio-service".to_string()),
+            hidden: true,
+        };
+        assert!(!manager.set_access_policy("audio.calibration", calibration.clone()));
+        assert!(!manager.set_access_policy("audio.eq*", AccessPolicy::default()));
+        assert!(manager.set_access_policy_as("audio-service", "audio.calibration", calibration));
+        assert!(!manager.set_access_policy("audio.calibration", AccessPolicy::default()));
+        manager.set_access_policy("build.type", AccessPolicy {
+            mode: AccessMode::ReadOnly,
+            ..Default::default()
+        });
+
+        assert_eq!(manager.try_set_parameter("audio.volume", 5), SetOutcome::Denied);
+        assert_eq!(manager.try_set_parameter_as("video-service", "audio.volume", 5), SetOutcome::Denied);
+        assert_eq!(manager.try_set_parameter_as("audio-service", "audio.volume", 5), SetOutcome::Stored);
+        assert_eq!(manager.get_parameter_int("audio.volume", 0).unwrap(), 5);
+
+        assert_eq!(manager.try_set_parameter_as("audio-service", "audio.calibration", 7), SetOutcome::Stored);
+        assert_eq!(manager.try_set_parameter_as("audio-service", "audio.calibration", 8), SetOutcome::ReadOnly);
+        assert_eq!(manager.try_set_parameter("build.type", "user"), SetOutcome::ReadOnly);
+        assert_eq!(manager.try_set_parameter("other", 1), SetOutcome::Stored);
+
+        // the legacy prefix and read_only rules still mean write-once
+        assert_eq!(manager.get_access_policy("ro.serial").mode, AccessMode::WriteOnce);
+        assert_eq!(manager.get_access_policy("other"), AccessPolicy::default());
+
+        // owned keys are not reset by anonymous callers
+        assert!(manager.reset_all().contains(&"other".to_string()));
+        assert_eq!(manager.get_parameter_int("audio.volume", 0).unwrap(), 5);
+
+        // hidden keys are readable by key but left out of exports
+        assert!(manager.is_hidden("audio.calibration"));
+        assert_eq!(manager.get_parameter_int("audio.calibration", 0).unwrap(), 7);
+        let mut typed = Vec::new();
+        manager.store_to_typed_stream(&mut typed).unwrap();
+        assert_eq!(String::from_utf8(typed).unwrap(), "audio.volume = Int(5)\n");
+        assert!(!manager.to_json(false).contains("calibration"));
+
+        // rules covering owned keys need the owner's token
+        let narrow = ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::RangedInt { min: 0, max: 3 },
+            ..Default::default()
+        };
+        let read_only = ParamRule {
+            read_only: true,
+            ..Default::default()
+        };
+        assert!(manager.set_parameter_rule("audio.volume", read_only.clone()).is_empty());
+        assert!(manager.set_parameter_rule("*", read_only.clone()).is_empty());
+        assert_eq!(
+            manager.set_parameter_rule_as("video-service", "audio.*", read_only),
+            Err(ParamError::Denied { key: "audio.*".to_string() })
+        );
+        assert_eq!(manager.try_set_parameter_as("audio-service", "audio.volume", 6), SetOutcome::Stored);
+        assert_eq!(manager.try_set_parameter("other", 2), SetOutcome::Stored);
+
+        // a new rule reports owned values it would change instead of rewriting them
+        let revalidations = manager.set_parameter_rule_as("audio-service", "audio.volume", narrow).expect("Refused");
+        assert!(matches!(&revalidations[..], [Revalidation::Invalid { reason: RejectReason::Protected, .. }]));
+        assert_eq!(manager.get_parameter_int("audio.volume", 0).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_subtree() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter("audio.volume", 5);
+        manager.set_parameter("audio.eq.bass", 2);
+        manager.set_parameter("audio.eq.treble", -1);
+        manager.set_parameter("audio.secret", "x");
+        manager.set_parameter("video.width", 1920);
+        manager.set_access_policy("audio.secret", AccessPolicy {
+            hidden: true,
+            ..Default::default()
+        });
+
+        assert_eq!(manager.keys(), vec!["audio.eq.bass", "audio.eq.treble", "audio.volume", "video.width"]);
+        assert_eq!(manager.keys_with_prefix("audio.eq."), vec!["audio.eq.bass", "audio.eq.treble"]);
+        assert_eq!(manager.children(""), vec!["audio", "video"]);
+        assert_eq!(manager.children("audio"), vec!["eq", "volume"]);
+        assert_eq!(manager.children("audio.eq."), vec!["bass", "treble"]);
+        let mut siblings = ParameterManager::new();
+        for key in ["audio.eq", "audio.eq-preset", "audio.eq.bass"] {
+            siblings.set_parameter(key, 1);
+        }
+        assert_eq!(siblings.children("audio"), vec!["eq", "eq-preset"]);
+
+        let subtree = manager.get_subtree("audio.eq.");
+        assert_eq!(subtree.len(), 2);
+        assert_eq!(subtree["audio.eq.bass"], ParamValue::Int(2));
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audio.txt");
+        manager.store_subtree_to_file("audio.", &path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "audio.eq.bass = Int(2)\naudio.eq.treble = Int(-1)\naudio.volume = Int(5)\n"
+        );
+
+        let mut other = ParameterManager::new();
+        other.set_parameter("backup.audio.volume", 9);
+        assert_eq!(other.restore_subtree_from_file(&path, "audio.eq.", Some("backup.audio.eq."), false).unwrap(), 2);
+        assert_eq!(other.restore_subtree_from_file(&path, "audio.", Some("backup.audio."), false).unwrap(), 0);
+        assert_eq!(other.get_parameter_int("backup.audio.volume", 0).unwrap(), 9);
+        assert_eq!(other.get_parameter_int("backup.audio.eq.treble", 0).unwrap(), -1);
+
+        assert_eq!(manager.remove_subtree("audio.eq."), vec!["audio.eq.bass", "audio.eq.treble"]);
+        assert_eq!(manager.keys_with_prefix("audio."), vec!["audio.volume"]);
+        // hidden keys are not removed either
+        assert_eq!(manager.remove_subtree("audio."), vec!["audio.volume"]);
+        assert_eq!(manager.get_parameter_string("audio.secret", ""), "x");
+    }
+
+    #[test]
+    fn test_listener_patterns() {
+        let mut manager = ParameterManager::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listen = |manager: &mut ParameterManager, pattern: &str| {
+            let received = received.clone();
+            let name = pattern.to_string();
+            manager.register_callback(pattern, move |key, _| received.lock().unwrap().push((name.clone(), key)))
+        };
+        listen(&mut manager, "audio.*.volume");
+        listen(&mut manager, "audio.**");
+        let nested = listen(&mut manager, "**.volume");
+        listen(&mut manager, "audio.eq*.gain");
+        listen(&mut manager, "audio.*");
+        let regex_id = {
+            let received = received.clone();
+            manager.register_regex_callback(r"^video\.(width|height)$", move |key, _| {
+                received.lock().unwrap().push(("regex".to_string(), key))
+            }).unwrap()
+        };
+        let take = || {
+            let mut names: Vec<String> = received.lock().unwrap().drain(..).map(|(name, _)| name).collect();
+            names.sort();
+            names
+        };
+
+        manager.set_parameter("audio.speaker.volume", 1);
+        assert_eq!(take(), vec!["**.volume", "audio.*", "audio.**", "audio.*.volume"]);
+        manager.set_parameter("audio.eq1.gain", 1);
+        assert_eq!(take(), vec!["audio.*", "audio.**", "audio.eq*.gain"]);
+        // '**' also matches no segment at all, a trailing '*' needs something after the prefix
+        manager.set_parameter("audio", 1);
+        assert_eq!(take(), vec!["audio.**"]);
+        manager.set_parameter("volume", 1);
+        assert_eq!(take(), vec!["**.volume"]);
+        manager.set_parameter("video.width", 1);
+        manager.set_parameter("video.depth", 1);
+        assert_eq!(take(), vec!["regex"]);
+
+        assert!(manager.unregister_callback(nested));
+        assert!(manager.unregister_callback(regex_id));
+        assert!(!manager.unregister_callback(regex_id));
+        manager.set_parameter("audio.speaker.volume", 2);
+        manager.set_parameter("video.width", 2);
+        assert_eq!(take(), vec!["audio.*", "audio.**", "audio.*.volume"]);
+
+        // inline flags are kept, a bad pattern is an error, and many expressions stay matched
+        let mut manager = ParameterManager::new();
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        assert!(manager.register_regex_callback("(", |_, _| {}).is_err());
+        let ids: Vec<usize> = (0..150)
+            .map(|i| {
+                let hits = hits.clone();
+                manager.register_regex_callback(&format!(r"(?i)^KEY\.{}$", i), move |_, _| hits.lock().unwrap().push(i)).unwrap()
+            })
+            .collect();
+        assert!(manager.unregister_callback(ids[70]));
+        for i in [0, 70, 100, 149] {
+            manager.set_parameter(&format!("key.{}", i), 1);
+        }
+        assert_eq!(*hits.lock().unwrap(), vec![0, 100, 149]);
+    }
+
+    #[test]
+    fn test_queued_dispatch() {
+        let executors: Vec<DispatchMode> = vec![
+            DispatchMode::Thread,
+            // one thread per job, so deliveries of different keys run in parallel
+            DispatchMode::Executor(Arc::new(|job| {
+                std::thread::spawn(job);
+            })),
+        ];
+        for mode in executors {
+            let manager = Arc::new(Mutex::new(ParameterManager::new()));
+            manager.lock().unwrap().set_dispatch_mode(mode);
+
+            // setting another key through the shared manager from a callback must not deadlock
+            let weak = Arc::downgrade(&manager);
+            manager.lock().unwrap().register_callback("source", move |_, value| {
+                if let Some(manager) = weak.upgrade() {
+                    manager.lock().unwrap().set_parameter("mirror", value);
+                }
+            });
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let sink = received.clone();
+            manager.lock().unwrap().register_callback("mirror", move |_, value| {
+                sink.lock().unwrap().push(value.parse::<i32>().unwrap());
+            });
+
+            for i in 0..50 {
+                manager.lock().unwrap().set_parameter("source", i);
+            }
+            ParameterManager::wait_for_notifications(&manager);
+            assert_eq!(*received.lock().unwrap(), (0..50).collect::<Vec<i32>>());
+            assert_eq!(manager.lock().unwrap().get_parameter_int("mirror", -1).unwrap(), 49);
+        }
+
+        // switching modes under the lock a queued callback waits for must not deadlock
+        let manager = Arc::new(Mutex::new(ParameterManager::new()));
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut locked = manager.lock().unwrap();
+            locked.set_dispatch_mode(DispatchMode::Thread);
+            let weak = Arc::downgrade(&manager);
+            locked.register_callback("source", move |_, value| {
+                if let Some(manager) = weak.upgrade() {
+                    drop(manager.lock().unwrap());
+                }
+                sender.send(value).unwrap();
+            });
+            locked.set_parameter("source", 1);
+            std::thread::sleep(Duration::from_millis(20));
+            locked.set_dispatch_mode(DispatchMode::Inline);
+        }
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("codec.bitrate", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::Ranged,
+            range_min: 8.0,
+            range_max: 320.0,
+            ..Default::default()
+        });
+        manager.set_parameter("codec.name", "aac");
+        manager.set_parameter("ro.codec.vendor", "acme");
+
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let sink = notified.clone();
+        manager.register_callback("codec.*", move |key, value| sink.lock().unwrap().push((key, value)));
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let sink = batches.clone();
+        manager.register_batch_callback(move |changes| sink.lock().unwrap().push(changes));
+
+        // one bad value fails the whole transaction
+        let errors = manager
+            .transaction(|tx| {
+                tx.set("codec.name", "opus");
+                tx.set("codec.bitrate", "fast");
+                tx.set("ro.codec.vendor", "other");
+            })
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], ParamError::Rejected { key, .. } if key == "codec.bitrate"));
+        assert_eq!(errors[1], ParamError::ReadOnly { key: "ro.codec.vendor".to_string() });
+        assert_eq!(manager.get_parameter_string("codec.name", ""), "aac");
+        assert!(notified.lock().unwrap().is_empty());
+
+        let changed = manager
+            .transaction(|tx| {
+                tx.set("codec.name", "mp3");
+                tx.set("codec.name", "opus");
+                assert_eq!(tx.get("codec.name"), Some(&ParamValue::from("opus")));
+                tx.set("codec.bitrate", 500);
+                tx.set("codec.channels", 2);
+                tx.set("ro.codec.vendor", "acme");
+            })
+            .unwrap();
+        assert_eq!(changed, vec!["codec.bitrate", "codec.channels", "codec.name"]);
+        assert_eq!(manager.get_parameter_int("codec.bitrate", 0).unwrap(), 320);
+        assert_eq!(
+            *notified.lock().unwrap(),
+            vec![
+                ("codec.bitrate".to_string(), "320".to_string()),
+                ("codec.channels".to_string(), "2".to_string()),
+                ("codec.name".to_string(), "opus".to_string()),
+            ]
+        );
+        let batches = batches.lock().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0][0], ("codec.bitrate".to_string(), ParamValue::Int(320)));
+    }
+
+    #[test]
+    fn test_veto_callback() {
+        let mut manager = ParameterManager::new();
+        manager.set_parameter("audio.state", "playing");
+        manager.set_parameter_rule("audio.samplerate", ParamRule {
+            param_type: ParamType::TypeInt,
+            enum_vals: ["44100", "48000"].iter().map(|s| s.to_string()).collect(),
+            range: ParamRange::RangeEnum,
+            ..Default::default()
+        });
+        manager.register_veto_callback("audio.samplerate", |manager, _, _| {
+            if manager.get_parameter_string("audio.state", "") == "stopped" {
+                ChangeDecision::Accept
+            } else {
+                ChangeDecision::Reject("audio must be stopped".to_string())
+            }
+        });
+        // rewrites are checked against the rule again
+        let rewrite = manager.register_veto_callback("audio.*", |_, _, value| match value {
+            ParamValue::Int(1) => ChangeDecision::Rewrite(ParamValue::Int(2)),
+            ParamValue::Int(5) => ChangeDecision::Rewrite(ParamValue::Int(50)),
+            _ => ChangeDecision::Accept,
+        });
+
+        assert_eq!(
+            manager.try_set_parameter("audio.samplerate", 48000),
+            SetOutcome::Rejected { reason: RejectReason::Vetoed("audio must be stopped".to_string()) }
+        );
+        manager.set_parameter("audio.state", "stopped");
+        assert_eq!(manager.try_set_parameter("audio.samplerate", 48000), SetOutcome::Stored);
+        assert_eq!(
+            manager.try_set_parameter("audio.gain", 1),
+            SetOutcome::Clamped { from: ParamValue::Int(1), to: ParamValue::Int(2) }
+        );
+        manager.set_parameter_rule("audio.gain", ParamRule {
+            param_type: ParamType::TypeInt,
+            range: ParamRange::Ranged,
+            range_min: 0.0,
+            range_max: 10.0,
+            ..Default::default()
+        });
+        assert_eq!(
+            manager.try_set_parameter("audio.gain", 5),
+            SetOutcome::Clamped { from: ParamValue::Int(5), to: ParamValue::Int(10) }
+        );
+
+        // transactions report vetoes too
+        manager.set_parameter("audio.state", "playing");
+        let errors = manager.transaction(|tx| tx.set("audio.samplerate", 48000)).unwrap_err();
+        assert!(matches!(&errors[0], ParamError::Rejected { reason: RejectReason::Vetoed(_), .. }));
+        assert_eq!(manager.get_parameter_int("audio.samplerate", 0).unwrap(), 48000);
+        // and see the values staged with it
+        assert_eq!(
+            manager.transaction(|tx| {
+                tx.set("audio.state", "stopped");
+                tx.set("audio.samplerate", 44100);
+            }),
+            Ok(vec!["audio.samplerate".to_string(), "audio.state".to_string()])
+        );
+
+        assert!(manager.unregister_callback(rewrite));
+        assert_eq!(manager.try_set_parameter("audio.gain", 1), SetOutcome::Stored);
+
+        // reloads and restores consult a veto listener once per value
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("params.txt");
+        std::fs::write(&path, "net.port = Int(90)\n").unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut manager = ParameterManager::new();
+        manager.set_parameter_rule("net.port", ParamRule {
+            param_type: ParamType::TypeInt,
+            ..Default::default()
+        });
+        manager.register_veto_callback("net.port", move |_, _, value| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            ChangeDecision::Rewrite(ParamValue::Int(value.as_f64().unwrap() as i64 + 1))
+        });
+        manager.restore_from_typed_stream(&mut Cursor::new("net.port = Int(80)\n"), true).unwrap();
+        assert_eq!((calls.load(Ordering::SeqCst), manager.get_parameter_int("net.port", 0).unwrap()), (1, 81));
+        assert_eq!(manager.reload_from_file(&path).unwrap().changed, vec!["net.port"]);
+        assert_eq!((calls.load(Ordering::SeqCst), manager.get_parameter_int("net.port", 0).unwrap()), (2, 91));
+    }
+
+    #[test]
+    fn test_undo_and_snapshots() {
+        let mut manager = ParameterManager::new();
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let sink = notified.clone();
+        manager.register_callback("*", move |key, value| sink.lock().unwrap().push(format!("{}={}", key, value)));
+
+        manager.set_parameter("eq.bass", 1);
+        manager.set_parameter("eq.bass", 2);
+        manager
+            .transaction(|tx| {
+                tx.set("eq.bass", 3);
+                tx.set("eq.treble", 4);
+            })
+            .unwrap();
+        assert!(!manager.can_redo());
+
+        // the transaction is undone as one step
+        assert_eq!(manager.undo().changed, vec!["eq.bass", "eq.treble"]);
+        assert_eq!(manager.get_parameter_int("eq.bass", 0).unwrap(), 2);
+        assert_eq!(manager.get_parameter_value("eq.treble"), None);
+        assert_eq!(manager.undo().changed, vec!["eq.bass"]);
+        assert_eq!(manager.redo().changed, vec!["eq.bass"]);
+        assert_eq!(manager.get_parameter_int("eq.bass", 0).unwrap(), 2);
+        assert!(manager.can_redo());
+        // a new change drops what could be redone
+        manager.set_parameter("eq.mid", 5);
+        assert!(!manager.can_redo());
+
+        manager.snapshot("flat");
+        manager.set_parameter("eq.bass", 9);
+        manager.set_parameter("eq.loudness", true);
+        manager.snapshot("boosted");
+        assert_eq!(
+            manager.diff_snapshot("flat", "boosted").unwrap(),
+            vec![
+                ParamDiff { key: "eq.bass".to_string(), before: Some(ParamValue::Int(2)), after: Some(ParamValue::Int(9)) },
+                ParamDiff { key: "eq.loudness".to_string(), before: None, after: Some(ParamValue::Bool(true)) },
+            ]
+        );
+
+        notified.lock().unwrap().clear();
+        assert_eq!(manager.restore_snapshot("flat").unwrap().changed, vec!["eq.bass", "eq.loudness"]);
+        assert_eq!(*notified.lock().unwrap(), vec!["eq.bass=2"]);
+        assert_eq!(manager.get_parameter_value("eq.loudness"), None);
+        assert_eq!(manager.get_parameter_int("eq.mid", 0).unwrap(), 5);
+        assert_eq!(manager.restore_snapshot("missing"), None);
+        assert_eq!(manager.undo().changed, vec!["eq.bass", "eq.loudness"]);
+        assert_eq!(manager.get_parameter_int("eq.bass", 0).unwrap(), 9);
+
+        manager.set_history_limit(1);
+        manager.set_parameter("eq.bass", 1);
+        manager.set_parameter("eq.bass", 0);
+        assert_eq!(manager.undo().changed, vec!["eq.bass"]);
+        assert!(!manager.can_undo());
+
+        // history does not get around owners and write-once keys
+        manager.set_history_limit(10);
+        manager.set_access_policy("audio.volume", AccessPolicy {
+            owner: Some("audio".to_string()),
+            ..Default::default()
+        });
+        manager.set_parameter_as("audio", "audio.volume", 5);
+        manager.set_parameter("ro.serial", "abc");
+        manager.snapshot("protected");
+        let report = manager.undo();
+        assert_eq!((report.changed, report.refused), (vec![], vec!["ro.serial".to_string()]));
+        let report = manager.undo();
+        assert_eq!((report.changed, report.refused), (vec![], vec!["audio.volume".to_string()]));
+        assert_eq!(manager.get_parameter_int("audio.volume", 0).unwrap(), 5);
+        assert_eq!(manager.try_set_parameter("ro.serial", "evil"), SetOutcome::ReadOnly);
+        manager.set_parameter("eq.bass", 7);
+        let report = manager.restore_snapshot("flat").unwrap();
+        assert_eq!(report.changed, vec!["eq.bass", "eq.loudness"]);
+        assert_eq!(report.refused, vec!["audio.volume", "ro.serial"]);
+        assert_eq!(manager.get_parameter_string("ro.serial", ""), "abc");
+    }
+
+    #[test]
+    fn test_journal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("params.journal");
+        let options = JournalOptions {
+            compact_after: 4,
+            ..Default::default()
+        };
+
+        let mut manager = ParameterManager::new();
+        assert_eq!(manager.open_journal(&path, options.clone()).unwrap(), 0);
+        manager.set_parameter("audio.volume", 5);
+        manager.set_journal_tag(Some("tuning-ui"));
+        manager.set_parameter("audio.volume", 7);
+        manager.set_parameter("audio.gain", 1.5);
+        manager.set_journal_tag(None);
+        manager.remove_subtree("audio.gain");
+        manager.flush().unwrap();
+
+        let records = read_journal(&path).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].key, "audio.volume");
+        assert_eq!(records[1].old, Some(ParamValue::Int(5)));
+        assert_eq!(records[1].new, Some(ParamValue::Int(7)));
+        assert_eq!(records[1].tag.as_deref(), Some("tuning-ui"));
+        assert_eq!(records[3].new, None);
+        assert_eq!(records[3].tag, None);
+
+        // a record cut short by a crash is dropped on the next start
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"ts\":1,\"key\":\"audio.volume\",\"new\":9").unwrap();
+        drop(file);
+        let mut restored = ParameterManager::new();
+        assert_eq!(restored.open_journal(&path, options.clone()).unwrap(), 4);
+        assert_eq!(restored.get_parameter_int("audio.volume", 0).unwrap(), 7);
+        assert_eq!(restored.get_parameter_value("audio.gain"), None);
+
+        // the fifth record goes past the threshold and the journal becomes a snapshot
+        restored.set_parameter("audio.mode", "high");
+        restored.flush().unwrap();
+        assert!(read_journal(&path).unwrap().is_empty());
+        restored.set_parameter("audio.volume", 8);
+        assert_eq!(read_journal(&path).unwrap().len(), 1);
+        restored.close_journal().unwrap();
+
+        let mut replayed = ParameterManager::new();
+        assert_eq!(replayed.open_journal(&path, options).unwrap(), 2);
+        assert_eq!(replayed.get_parameter_string("audio.mode", ""), "high");
+        assert_eq!(replayed.get_parameter_int("audio.volume", 0).unwrap(), 8);
+
+        // corruption before the last line is an error
+        std::fs::write(&path, "garbage\n{\"ts\":1,\"key\":\"a\",\"new\":1}\n").unwrap();
+        assert!(ParameterManager::new().open_journal(&path, JournalOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_metadata_introspection() {
+        let schema_text = r#"{
+            "rules": {
+                "audio.volume": { "type": "Int", "min": 0, "max": 100, "default": 50,
+                                  "description": "Master volume", "display_name": "Volume", "unit": "%",
+                                  "tags": ["audio", "output"] },
+                "audio.legacy_gain": { "type": "Float", "visibility": "developer",
+                                       "deprecated": "use audio.volume" }
+            }
+        }"#;
+        let schema = Schema::from_reader(&mut Cursor::new(schema_text)).unwrap();
+        let mut manager = ParameterManager::new();
+        manager.apply_schema(&schema);
+        manager.set_parameter_metadata("video.*", ParamMetadata {
+            description: "Video output".to_string(),
+            visibility: Visibility::Advanced,
+            ..Default::default()
+        });
+        manager.set_parameter("video.width", 1920);
+        manager.set_parameter("audio.volume", 70);
+
+        let infos = manager.describe_parameters();
+        let keys: Vec<&str> = infos.iter().map(|info| info.key.as_str()).collect();
+        assert_eq!(keys, vec!["audio.legacy_gain", "audio.volume", "video.width"]);
+
+        let volume = &infos[1];
+        assert_eq!(volume.metadata.display_name.as_deref(), Some("Volume"));
+        assert_eq!(volume.metadata.description, "Master volume");
+        assert_eq!(volume.metadata.unit.as_deref(), Some("%"));
+        assert_eq!(volume.metadata.tags, vec!["audio", "output"]);
+        assert_eq!(volume.value, Some(ParamValue::Int(70)));
+        assert_eq!(volume.default_value, Some(ParamValue::Int(50)));
+        assert_eq!(volume.rule.as_ref().unwrap().param_type, ParamType::TypeInt);
+
+        assert_eq!(infos[0].metadata.deprecated.as_deref(), Some("use audio.volume"));
+        assert_eq!(infos[0].metadata.visibility, Visibility::Developer);
+        assert_eq!(infos[0].value, None);
+        assert!(infos[2].rule.is_none());
+        assert_eq!(infos[2].metadata.visibility, Visibility::Advanced);
+
+        // metadata of ruled keys goes along with the rules in JSON
+        let mut copy = ParameterManager::new();
+        copy.restore_from_json(&mut Cursor::new(manager.to_json(true)), true).unwrap();
+        assert_eq!(copy.get_parameter_metadata("audio.volume"), manager.get_parameter_metadata("audio.volume"));
+    }
+
+    #[test]
+    fn test_cli() {
+        let dir = tempdir().unwrap();
+        let run = |args: &[&str]| {
+            let output = std::process::Command::new(env!("CARGO_BIN_EXE_datamanager"))
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap();
+            (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+        };
+        std::fs::write(
+            dir.path().join("schema.json"),
+            r#"{"rules": {"audio.volume": {"type": "Int", "min": 0, "max": 10}, "net.timeout": {"type": "Int", "default": 30}}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("a.txt"), "audio.volume = Int(5)\nvideo.width = Int(1920)\n").unwrap();
+
+        assert_eq!(run(&["get", "a.txt", "audio.volume"]), (0, "5\n".to_string()));
+        assert_eq!(run(&["set", "a.txt", "audio.volume", "50", "--schema", "schema.json"]).0, 0);
+        assert_eq!(run(&["set", "a.txt", "audio.volume", "loud", "--schema", "schema.json"]).0, 1);
+        assert_eq!(run(&["list", "a.txt", "audio."]), (0, "audio.volume = 10\n".to_string()));
+        // schema defaults are reported, not written into the file
+        assert_eq!(run(&["get", "a.txt", "net.timeout", "--schema", "schema.json"]), (0, "30\n".to_string()));
+        assert!(!std::fs::read_to_string(dir.path().join("a.txt")).unwrap().contains("net.timeout"));
+        assert_eq!(run(&["list", "nosuch.txt"]).0, 2);
+        assert_eq!(run(&["set", "nosuch.txt", "audio.volume", "1"]).0, 2);
+
+        assert_eq!(run(&["convert", "a.txt", "b.json"]).0, 0);
+        assert_eq!(run(&["set", "b.json", "video.width", "1280"]).0, 0);
+        // the rules written by convert survive set
+        assert!(std::fs::read_to_string(dir.path().join("b.json")).unwrap().contains("\"rules\""));
+        assert_eq!(run(&["diff", "a.txt", "b.json"]), (1, "~ video.width = 1920 -> 1280\n".to_string()));
+        assert_eq!(run(&["validate", "a.txt", "--schema", "schema.json"]), (1, "video.width: not declared in the schema\n".to_string()));
+        assert_eq!(run(&["get", "a.txt"]).0, 2);
+
+        // without a rule a new key is typed by its text
+        std::fs::write(dir.path().join("n.txt"), "").unwrap();
+        for (key, text) in [("vol", "5"), ("ratio", "0.5"), ("mute", "true"), ("name", "5a")] {
+            assert_eq!(run(&["set", "n.txt", key, text]).0, 0);
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("n.txt")).unwrap(),
+            "mute = Bool(true)\nname = String(\"5a\")\nratio = Float(0.5)\nvol = Int(5)\n"
+        );
+    }
+
+    #[test]
+    fn test_socket_server() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("params.sock");
+        let manager = Arc::new(Mutex::new(ParameterManager::new()));
+        {
+            let mut manager = manager.lock().unwrap();
+            manager.set_parameter_rule("audio.volume", ParamRule {
+                param_type: ParamType::TypeInt,
+                range: ParamRange::RangedInt { min: 0, max: 10 },
+                ..Default::default()
+            });
+            manager.set_parameter("audio.volume", 5);
+            manager.set_parameter("video.mode", "hd");
+            manager.set_access_policy("audio.owner", AccessPolicy {
+                owner: Some("audio".to_string()),
+                ..Default::default()
+            });
+        }
+        let mut server = ParamServer::bind(&manager, &path).unwrap();
+        let client = Arc::new(ParamClient::connect(server.path()).unwrap());
+
+        assert_eq!(client.get_parameter_value("audio.volume").unwrap(), Some(ParamValue::Int(5)));
+        assert_eq!(client.get_parameter_value("missing").unwrap(), None);
+        assert_eq!(client.keys().unwrap(), vec!["audio.volume", "video.mode"]);
+        assert_eq!(client.keys_with_prefix("video.").unwrap(), vec!["video.mode"]);
+
+        assert_eq!(client.try_set_parameter("audio.volume", 70).unwrap(), SetOutcome::Clamped {
+            from: ParamValue::Int(70),
+            to: ParamValue::Int(10),
+        });
+        assert_eq!(manager.lock().unwrap().get_parameter_int("audio.volume", 0).unwrap(), 10);
+        assert_eq!(client.try_set_parameter("audio.owner", 1).unwrap(), SetOutcome::Denied);
+        assert_eq!(client.try_set_parameter_as("audio", "audio.owner", 1).unwrap(), SetOutcome::Stored);
+
+        // changes made in the serving process and by other clients are pushed
+        let (sender, receiver) = mpsc::channel();
+        let exact_sender = sender.clone();
+        client.register_callback("video.mode", move |key, value| exact_sender.send((key, value)).unwrap()).unwrap();
+        let subscription = client.register_callback("audio.*", move |key, value| sender.send((key, value)).unwrap()).unwrap();
+        manager.lock().unwrap().set_parameter("audio.volume", 3);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), ("audio.volume".to_string(), "3".to_string()));
+        let other = ParamClient::connect(&path).unwrap();
+        other.set_parameter("video.mode", "4k").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), ("video.mode".to_string(), "4k".to_string()));
+
+        // a callback may call the client back
+        let (volume_sender, volume_receiver) = mpsc::channel();
+        let weak = Arc::downgrade(&client);
+        let reading = client.register_callback("video.mode", move |_, _| {
+            if let Some(client) = weak.upgrade() {
+                volume_sender.send(client.get_parameter_value("audio.volume").unwrap()).unwrap();
+            }
+        }).unwrap();
+        other.set_parameter("video.mode", "uhd").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), ("video.mode".to_string(), "uhd".to_string()));
+        assert_eq!(volume_receiver.recv_timeout(Duration::from_secs(5)).unwrap(), Some(ParamValue::Int(3)));
+        assert!(client.unregister_callback(reading).unwrap());
+
+        assert!(client.unregister_callback(subscription).unwrap());
+        assert!(!client.unregister_callback(subscription).unwrap());
+        other.set_parameter("audio.volume", 4).unwrap();
+        other.set_parameter("video.mode", "sd").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), ("video.mode".to_string(), "sd".to_string()));
+        assert!(receiver.try_recv().is_err());
+
+        // a subscription id already in use is refused
+        {
+            use std::io::Read;
+            use std::os::unix::net::UnixStream;
+            let mut raw = UnixStream::connect(&path).unwrap();
+            let mut responses = Vec::new();
+            for _ in 0..2 {
+                let request = r#"{"id":1,"op":"subscribe","pattern":"audio.*"}"#;
+                raw.write_all(&(request.len() as u32).to_be_bytes()).unwrap();
+                raw.write_all(request.as_bytes()).unwrap();
+                let mut len = [0u8; 4];
+                raw.read_exact(&mut len).unwrap();
+                let mut payload = vec![0u8; u32::from_be_bytes(len) as usize];
+                raw.read_exact(&mut payload).unwrap();
+                responses.push(serde_json::from_slice::<serde_json::Value>(&payload).unwrap());
+            }
+            assert!(responses[0].get("error").is_none());
+            assert!(responses[1].get("error").is_some());
+        }
+
+        server.shutdown();
+        assert!(!path.exists());
+        assert!(client.get_parameter_value("audio.volume").is_err());
+    }
+
+    #[test]
+    fn test_channel_subscription() {
+        let manager = Arc::new(Mutex::new(ParameterManager::new()));
+        let change = |key: &str, value: &str| ParamChange {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+
+        // consumed from another thread without any shared closure state
+        let receiver = ParameterManager::subscribe(&manager, "audio.*");
+        let consumer = std::thread::spawn(move || receiver.iter().take(3).collect::<Vec<ParamChange>>());
+        for i in 0..3 {
+            manager.lock().unwrap().set_parameter("audio.volume", i);
+        }
+        manager.lock().unwrap().set_parameter("video.mode", "hd");
+        assert_eq!(consumer.join().unwrap(), vec![change("audio.volume", "0"), change("audio.volume", "1"), change("audio.volume", "2")]);
+
+        let oldest = ParameterManager::subscribe_with(&manager, "audio.*", SubscribeOptions {
+            capacity: Some(2),
+            overflow: OverflowPolicy::DropOldest,
+        });
+        let coalesced = ParameterManager::subscribe_with(&manager, "audio.*", SubscribeOptions {
+            capacity: Some(2),
+            overflow: OverflowPolicy::CoalescePerKey,
+        });
+        for (key, value) in [("audio.volume", 10), ("audio.balance", 1), ("audio.volume", 11), ("audio.volume", 12)] {
+            manager.lock().unwrap().set_parameter(key, value);
+        }
+        assert_eq!(oldest.try_iter().collect::<Vec<ParamChange>>(), vec![change("audio.volume", "11"), change("audio.volume", "12")]);
+        assert_eq!(oldest.dropped(), 2);
+        assert_eq!(coalesced.try_iter().collect::<Vec<ParamChange>>(), vec![change("audio.volume", "12"), change("audio.balance", "1")]);
+        assert_eq!(coalesced.dropped(), 0);
+        assert_eq!(coalesced.recv_timeout(Duration::from_millis(10)), Err(mpsc::RecvTimeoutError::Timeout));
+
+        // dropping the receiver unregisters its listener, dropping the manager disconnects it
+        drop(oldest);
+        manager.lock().unwrap().set_parameter("audio.volume", 13);
+        assert_eq!(coalesced.try_recv(), Ok(change("audio.volume", "13")));
+        drop(manager);
+        assert_eq!(coalesced.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+        assert_eq!(coalesced.recv(), Err(mpsc::RecvError));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_stream_subscription() {
+        use tokio_stream::StreamExt;
+
+        let manager = Arc::new(Mutex::new(ParameterManager::new()));
+        manager.lock().unwrap().set_parameter("audio.volume", 5);
+        let (mut watch, _subscription) = ParameterManager::watch_parameter(&manager, "audio.volume");
+        assert_eq!(*watch.borrow_and_update(), Some("5".to_string()));
+        let mut stream = ParameterManager::subscribe(&manager, "audio.**").into_stream();
+
+        let writer = manager.clone();
+        tokio::task::spawn_blocking(move || {
+            writer.lock().unwrap().set_parameter("audio.volume", 6);
+            writer.lock().unwrap().set_parameter("audio.eq.bass", 2);
+        });
+        watch.changed().await.unwrap();
+        assert_eq!(*watch.borrow(), Some("6".to_string()));
+        let key_of = |change: Option<ParamChange>| change.unwrap().key;
+        assert_eq!(key_of(stream.next().await), "audio.volume");
+        assert_eq!(key_of(stream.next().await), "audio.eq.bass");
+
+        drop(manager);
+        assert_eq!(stream.next().await, None);
+    }
+}